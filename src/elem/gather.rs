@@ -15,8 +15,16 @@ pub trait Gather {
     input:   &mut Vec<ChannelWrapper<Self::InputValue, Self::InputError>>,
     output:  &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
     stop:    &mut bool);
+
+  // called once when the scheduler shuts down
+  fn shutdown(&mut self) {}
 }
 
+// creates a fresh element when the task is restarted by the scheduler
+pub type GatherFactory<InputValue, InputError, OutputValue, OutputError> =
+  Box<dyn FnMut() -> Box<dyn Gather<InputValue=InputValue, InputError=InputError,
+                                    OutputValue=OutputValue, OutputError=OutputError>+Send>+Send>;
+
 pub fn new<InputValue: Send, InputError: Send, OutputValue: Send, OutputError: Send>(
     name            : &str,
     output_q_size   : usize,
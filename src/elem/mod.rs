@@ -8,6 +8,7 @@ pub mod gather;
 pub mod connectable;
 pub mod identified_input;
 pub mod counter;
+pub mod stream;
 pub mod wrap;
 
 #[cfg(test)]
@@ -9,8 +9,15 @@ pub trait Sink {
     &mut self,
     input:  &mut ChannelWrapper<Self::InputValue, Self::InputError>,
     stop:   &mut bool);
+
+  // called once when the scheduler shuts down
+  fn shutdown(&mut self) {}
 }
 
+// creates a fresh element when the task is restarted by the scheduler
+pub type SinkFactory<InputValue, InputError> =
+  Box<dyn FnMut() -> Box<dyn Sink<InputValue=InputValue, InputError=InputError>+Send>+Send>;
+
 pub fn new<InputValue: Send, InputError: Send>(
     name   : &str,
     sink   : Box<Sink<InputValue=InputValue, InputError=InputError>+Send>)
@@ -10,8 +10,15 @@ pub trait Source {
     &mut self,
     output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
     stop: &mut bool);
+
+  // called once when the scheduler shuts down
+  fn shutdown(&mut self) {}
 }
 
+// creates a fresh element when the task is restarted by the scheduler
+pub type SourceFactory<OutputValue, OutputError> =
+  Box<dyn FnMut() -> Box<dyn Source<OutputValue=OutputValue, OutputError=OutputError>+Send>+Send>;
+
 pub fn new<OutputValue: Send, OutputError: Send>(
     name            : &str,
     output_q_size   : usize,
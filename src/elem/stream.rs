@@ -0,0 +1,201 @@
+use lossyq::spsc::{Sender, Receiver};
+use super::super::{Message, ChannelWrapper};
+use super::super::scheduler::{self, OutputWaker};
+use super::source::{self, Source};
+use super::wrap::source_wrap;
+use std::collections::{VecDeque};
+use std::future::{Future};
+use std::marker::{PhantomData};
+use std::pin::{Pin};
+use std::sync::{Arc};
+use std::task::{Context, Poll, Waker};
+
+// the source task and its unconnected output channel
+type StreamTask<OutputValue, OutputError> =
+  (Box<source_wrap::SourceWrap<OutputValue, OutputError>>,
+   Box<ChannelWrapper<OutputValue, OutputError>>);
+
+// an asynchronous sequence of values, the same as the futures crate's
+// Stream, so those can be adapted with a few lines
+pub trait Stream {
+  type Item;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>>;
+}
+
+// the future of the next item of a stream
+pub struct Next<'a, S: 'a + ?Sized> {
+  stream: &'a mut S,
+}
+
+impl<'a, S: Stream + Unpin + ?Sized> Future for Next<'a, S> {
+  type Output = Option<S::Item>;
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+    Pin::new(&mut *self.stream).poll_next(cx)
+  }
+}
+
+pub fn next<'a, S: Stream + Unpin + ?Sized>(stream: &'a mut S) -> Next<'a, S> {
+  Next{ stream }
+}
+
+// the waker of the task while it is executed by the scheduler, so the
+// task is notified when the polled future can progress
+fn waker(cached: &mut Option<Waker>) -> Waker {
+  if cached.is_none() {
+    *cached = scheduler::task_waker();
+  }
+  match *cached {
+    Some(ref w) => w.clone(),
+    // not executed by a scheduler
+    None        => Waker::noop().clone(),
+  }
+}
+
+// polls the future each time it is executed and sends its output, then
+// stops the task
+pub struct FutureSource<OutputValue: Send, OutputError: Send> {
+  future:  Option<Pin<Box<dyn Future<Output=OutputValue>+Send>>>,
+  waker:   Option<Waker>,
+  phantom: PhantomData<OutputError>,
+}
+
+impl<OutputValue: Send, OutputError: Send> Source for FutureSource<OutputValue, OutputError> {
+  type OutputValue = OutputValue;
+  type OutputError = OutputError;
+
+  fn process(&mut self,
+             output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             stop: &mut bool)
+  {
+    let waker = waker(&mut self.waker);
+    let mut cx = Context::from_waker(&waker);
+    let ready = match self.future {
+      Some(ref mut f) => f.as_mut().poll(&mut cx),
+      None            => Poll::Pending,
+    };
+    if let Poll::Ready(value) = ready {
+      let mut value = Some(value);
+      output.put(|v| *v = value.take().map(Message::Value));
+      self.future = None;
+    }
+    if self.future.is_none() {
+      *stop = true;
+    }
+  }
+}
+
+// forwards the ready items of the stream each time it is executed, and
+// stops the task when the stream ends
+pub struct StreamSource<OutputValue: Send, OutputError: Send> {
+  stream:  Pin<Box<dyn Stream<Item=OutputValue>+Send>>,
+  // at most this many items are sent in one execution
+  batch:   usize,
+  waker:   Option<Waker>,
+  phantom: PhantomData<OutputError>,
+}
+
+impl<OutputValue: Send, OutputError: Send> Source for StreamSource<OutputValue, OutputError> {
+  type OutputValue = OutputValue;
+  type OutputError = OutputError;
+
+  fn process(&mut self,
+             output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             stop: &mut bool)
+  {
+    let waker = waker(&mut self.waker);
+    let mut cx = Context::from_waker(&waker);
+    for _i in 0..self.batch {
+      match self.stream.as_mut().poll_next(&mut cx) {
+        Poll::Ready(Some(value)) => {
+          let mut value = Some(value);
+          output.put(|v| *v = value.take().map(Message::Value));
+        },
+        Poll::Ready(None) => {
+          *stop = true;
+          return;
+        },
+        Poll::Pending => {
+          return;
+        },
+      }
+    }
+    // the stream may have more ready items, so it won't wake us
+    waker.wake_by_ref();
+  }
+}
+
+// the task should be added with the OnExternalEvent rule, it is notified
+// when the future can progress
+pub fn future_source<OutputValue: Send + 'static, OutputError: Send + 'static, F>(
+    name            : &str,
+    output_q_size   : usize,
+    future          : F)
+      -> StreamTask<OutputValue, OutputError>
+  where F: Future<Output=OutputValue> + Send + 'static
+{
+  source::new(name, output_q_size, Box::new(FutureSource::<OutputValue, OutputError>{
+    future:  Some(Box::pin(future)),
+    waker:   None,
+    phantom: PhantomData,
+  }))
+}
+
+// the task should be added with the OnExternalEvent rule, it is notified
+// when the stream has new items
+pub fn stream_source<OutputValue: Send + 'static, OutputError: Send + 'static, S>(
+    name            : &str,
+    output_q_size   : usize,
+    stream          : S)
+      -> StreamTask<OutputValue, OutputError>
+  where S: Stream<Item=OutputValue> + Send + 'static
+{
+  source::new(name, output_q_size, Box::new(StreamSource::<OutputValue, OutputError>{
+    stream:  Box::pin(stream),
+    batch:   if output_q_size == 0 { 1 } else { output_q_size },
+    waker:   None,
+    phantom: PhantomData,
+  }))
+}
+
+// the receiving end of a task's output channel for async code. it is
+// woken after the sender task was executed and sent something.
+pub struct ReceiverStream<Value: Send, Error: Send> {
+  receiver:  Receiver<Message<Value, Error>>,
+  // the messages of the last read, in order
+  buffer:    VecDeque<Message<Value, Error>>,
+  waker:     Arc<OutputWaker>,
+}
+
+impl<Value: Send, Error: Send> Stream for ReceiverStream<Value, Error> {
+  type Item = Message<Value, Error>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+    if this.buffer.is_empty() {
+      // registered before reading, so a message sent meanwhile wakes us
+      this.waker.register(cx.waker());
+      this.buffer.extend(this.receiver.iter());
+    }
+    match this.buffer.pop_front() {
+      Some(msg) => Poll::Ready(Some(msg)),
+      None      => Poll::Pending,
+    }
+  }
+}
+
+// nothing is pinned structurally
+impl<Value: Send, Error: Send> Unpin for ReceiverStream<Value, Error> {}
+
+pub fn receiver_stream<Value: Send, Error: Send>(
+    receiver  : Receiver<Message<Value, Error>>,
+    waker     : Arc<OutputWaker>)
+      -> ReceiverStream<Value, Error>
+{
+  ReceiverStream{
+    receiver,
+    buffer:    VecDeque::new(),
+    waker,
+  }
+}
@@ -5,14 +5,18 @@ use super::super::super::{Task, Message, ChannelWrapper, ChannelId,
 use super::super::connectable::{Connectable};
 use super::super::identified_input::{IdentifiedInput};
 use super::super::counter::{OutputCounter, InputCounter};
-use super::super::filter::{Filter};
+use super::super::filter::{Filter, FilterFactory};
+use super::restartable::{self, Restartable};
+use super::super::super::scheduler;
+use super::staging::{self, Staging};
 
 pub struct FilterWrap<InputValue: Send, InputError: Send,
                       OutputValue: Send, OutputError: Send> {
   name         : String,
-  state        : Box<Filter<InputValue=InputValue, InputError=InputError,
-                            OutputValue=OutputValue, OutputError=OutputError>+Send>,
+  state        : Restartable<dyn Filter<InputValue=InputValue, InputError=InputError,
+                                        OutputValue=OutputValue, OutputError=OutputError>+Send>,
   input_rx     : ChannelWrapper<InputValue, InputError>,
+  input_staging: Staging<InputValue, InputError>,
   output_tx    : Sender<Message<OutputValue, OutputError>>,
 }
 
@@ -24,7 +28,18 @@ pub fn new<InputValue: Send, InputError: Send, OutputValue: Send, OutputError: S
              output_tx    : Sender<Message<OutputValue, OutputError>>)
     -> FilterWrap<InputValue, InputError, OutputValue, OutputError>
 {
-  FilterWrap{ name: name, state: state, input_rx: input_rx, output_tx: output_tx }
+  FilterWrap{ name, state: restartable::new(state), input_rx,
+              input_staging: staging::new(), output_tx }
+}
+
+impl<InputValue: Send, InputError: Send, OutputValue: Send, OutputError: Send>
+    FilterWrap<InputValue, InputError, OutputValue, OutputError>
+{
+  pub fn set_factory(&mut self,
+                     factory: FilterFactory<InputValue, InputError, OutputValue, OutputError>)
+  {
+    self.state.set_factory(factory);
+  }
 }
 
 impl<InputValue: Send, InputError: Send, OutputValue: Send, OutputError: Send> IdentifiedInput
@@ -87,8 +102,24 @@ impl<InputValue: Send, InputError: Send, OutputValue: Send, OutputError: Send> T
     for FilterWrap<InputValue, InputError, OutputValue, OutputError>
 {
   fn execute(&mut self, stop: &mut bool) {
-    self.state.process(&mut self.input_rx, &mut self.output_tx, stop);
+    let max_messages = scheduler::budget().max_messages;
+    self.input_staging.enter(&mut self.input_rx, max_messages);
+    self.state.get().process(&mut self.input_rx, &mut self.output_tx, stop);
+    self.input_staging.leave(&mut self.input_rx);
+  }
+
+  fn shutdown(&mut self) {
+    self.state.get().shutdown();
   }
+
+  fn restart(&mut self) -> bool {
+    self.state.restart()
+  }
+
+  fn pending_messages(&self) -> usize {
+    self.input_staging.pending(&self.input_rx)
+  }
+
   fn name(&self) -> &String { &self.name }
   fn input_count(&self) -> usize { 1 }
   fn output_count(&self) -> usize { 1 }
@@ -5,14 +5,18 @@ use super::super::super::{Task, Message, ChannelWrapper, ChannelId,
 use super::super::connectable::{ConnectableN};
 use super::super::identified_input::{IdentifiedInput};
 use super::super::counter::{OutputCounter, InputCounter};
-use super::super::gather::{Gather};
+use super::super::gather::{Gather, GatherFactory};
+use super::restartable::{self, Restartable};
+use super::super::super::scheduler;
+use super::staging::{self, Staging};
 
 pub struct GatherWrap<InputValue: Send, InputError: Send,
                       OutputValue: Send, OutputError: Send> {
   name           : String,
-  state          : Box<Gather<InputValue=InputValue, InputError=InputError,
-                              OutputValue=OutputValue, OutputError=OutputError>+Send>,
+  state          : Restartable<dyn Gather<InputValue=InputValue, InputError=InputError,
+                                          OutputValue=OutputValue, OutputError=OutputError>+Send>,
   input_rx_vec   : Vec<ChannelWrapper<InputValue, InputError>>,
+  input_staging  : Vec<Staging<InputValue, InputError>>,
   output_tx      : Sender<Message<OutputValue, OutputError>>,
 }
 
@@ -24,7 +28,18 @@ pub fn new<InputValue: Send, InputError: Send, OutputValue: Send, OutputError: S
           output_tx      : Sender<Message<OutputValue, OutputError>>)
     -> GatherWrap<InputValue, InputError, OutputValue, OutputError>
 {
-  GatherWrap{ name: name, state: state, input_rx_vec: input_rx_vec, output_tx: output_tx }
+  GatherWrap{ name, state: restartable::new(state), input_rx_vec,
+              input_staging: Vec::new(), output_tx }
+}
+
+impl<InputValue: Send, InputError: Send, OutputValue: Send, OutputError: Send>
+    GatherWrap<InputValue, InputError, OutputValue, OutputError>
+{
+  pub fn set_factory(&mut self,
+                     factory: GatherFactory<InputValue, InputError, OutputValue, OutputError>)
+  {
+    self.state.set_factory(factory);
+  }
 }
 
 impl<InputValue: Send, InputError: Send, OutputValue: Send, OutputError: Send> IdentifiedInput
@@ -91,10 +106,39 @@ impl<InputValue: Send, InputError: Send, OutputValue: Send, OutputError: Send> T
     for GatherWrap<InputValue, InputError, OutputValue, OutputError>
 {
   fn execute(&mut self, stop: &mut bool) {
-    self.state.process(&mut self.input_rx_vec,
-                       &mut self.output_tx,
-                       stop);
+    let max_messages = scheduler::budget().max_messages;
+    while self.input_staging.len() < self.input_rx_vec.len() {
+      self.input_staging.push(staging::new());
+    }
+    for (input, staging) in self.input_rx_vec.iter_mut().zip(self.input_staging.iter_mut()) {
+      staging.enter(input, max_messages);
+    }
+    self.state.get().process(&mut self.input_rx_vec,
+                             &mut self.output_tx,
+                             stop);
+    for (input, staging) in self.input_rx_vec.iter_mut().zip(self.input_staging.iter_mut()) {
+      staging.leave(input);
+    }
   }
+
+  fn shutdown(&mut self) {
+    self.state.get().shutdown();
+  }
+
+  fn restart(&mut self) -> bool {
+    self.state.restart()
+  }
+
+  fn pending_messages(&self) -> usize {
+    self.input_rx_vec.iter().enumerate().map(|(i, input)| {
+      match self.input_staging.get(i) {
+        Some(staging) => staging.pending(input),
+        // not executed since the input was connected
+        None          => staging::new().pending(input),
+      }
+    }).sum()
+  }
+
   fn name(&self) -> &String { &self.name }
   fn input_count(&self) -> usize { self.input_rx_vec.len() }
   fn output_count(&self) -> usize { 1 }
@@ -5,3 +5,5 @@ pub mod ymerge_wrap;
 pub mod sink_wrap;
 pub mod scatter_wrap;
 pub mod gather_wrap;
+mod staging;
+mod restartable;
@@ -0,0 +1,34 @@
+// the element of a wrapper with the factory that recreates it. with a
+// factory the task is restartable: the scheduler calls the factory to
+// replace the element, while the channels stay connected.
+pub struct Restartable<Element: ?Sized> {
+  element  : Box<Element>,
+  factory  : Option<Box<dyn FnMut() -> Box<Element>+Send>>,
+}
+
+pub fn new<Element: ?Sized>(element: Box<Element>) -> Restartable<Element> {
+  Restartable{ element, factory: None }
+}
+
+impl<Element: ?Sized> Restartable<Element> {
+  pub fn get(&mut self) -> &mut Element {
+    &mut self.element
+  }
+
+  pub fn set_factory(&mut self,
+                     factory: Box<dyn FnMut() -> Box<Element>+Send>)
+  {
+    self.factory = Some(factory);
+  }
+
+  // false without a factory, the element is kept then
+  pub fn restart(&mut self) -> bool {
+    match self.factory {
+      Some(ref mut factory) => {
+        self.element = factory();
+        true
+      },
+      None => false,
+    }
+  }
+}
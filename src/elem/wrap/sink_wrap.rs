@@ -4,12 +4,16 @@ use super::super::super::{Task, ChannelWrapper, ChannelId, SenderName,
 use super::super::connectable::{Connectable};
 use super::super::identified_input::{IdentifiedInput};
 use super::super::counter::{InputCounter};
-use super::super::sink::{Sink};
+use super::super::sink::{Sink, SinkFactory};
+use super::restartable::{self, Restartable};
+use super::super::super::scheduler;
+use super::staging::{self, Staging};
 
 pub struct SinkWrap<InputValue: Send, InputError: Send> {
   name      : String,
-  state     : Box<Sink<InputValue=InputValue, InputError=InputError>+Send>,
+  state     : Restartable<dyn Sink<InputValue=InputValue, InputError=InputError>+Send>,
   input_rx  : ChannelWrapper<InputValue, InputError>,
+  input_staging : Staging<InputValue, InputError>,
 }
 
 pub fn new<InputValue: Send, InputError: Send>(
@@ -18,7 +22,17 @@ pub fn new<InputValue: Send, InputError: Send>(
           input_rx  : ChannelWrapper<InputValue, InputError>)
     -> SinkWrap<InputValue, InputError>
 {
-  SinkWrap{ name: name, state: state, input_rx: input_rx }
+  SinkWrap{ name, state: restartable::new(state), input_rx, input_staging: staging::new() }
+}
+
+impl<InputValue: 'static+Send, InputError: 'static+Send>
+    SinkWrap<InputValue, InputError>
+{
+  pub fn set_factory(&mut self,
+                     factory: SinkFactory<InputValue, InputError>)
+  {
+    self.state.set_factory(factory);
+  }
 }
 
 impl<InputValue: 'static+Send, InputError: 'static+Send> IdentifiedInput
@@ -70,7 +84,22 @@ impl<InputValue: 'static+Send, InputError: 'static+Send> Task
     for SinkWrap<InputValue, InputError>
 {
   fn execute(&mut self, stop: &mut bool) {
-    self.state.process(&mut self.input_rx, stop);
+    let max_messages = scheduler::budget().max_messages;
+    self.input_staging.enter(&mut self.input_rx, max_messages);
+    self.state.get().process(&mut self.input_rx, stop);
+    self.input_staging.leave(&mut self.input_rx);
+  }
+
+  fn shutdown(&mut self) {
+    self.state.get().shutdown();
+  }
+
+  fn restart(&mut self) -> bool {
+    self.state.restart()
+  }
+
+  fn pending_messages(&self) -> usize {
+    self.input_staging.pending(&self.input_rx)
   }
 
   fn name(&self) -> &String { &self.name }
@@ -3,12 +3,13 @@ use super::super::super::{Task, Message, ChannelId, SenderName,
   SenderChannelId, ReceiverChannelId, ChannelPosition
 };
 use super::super::counter::{OutputCounter};
-use super::super::source::{Source};
+use super::super::source::{Source, SourceFactory};
+use super::restartable::{self, Restartable};
 
 pub struct SourceWrap<OutputValue: Send, OutputError: Send>
 {
   name       : String,
-  state      : Box<Source<OutputValue=OutputValue, OutputError=OutputError>+Send>,
+  state      : Restartable<dyn Source<OutputValue=OutputValue, OutputError=OutputError>+Send>,
   output_tx  : Sender<Message<OutputValue, OutputError>>,
 }
 
@@ -18,7 +19,17 @@ pub fn new<OutputValue: Send, OutputError: Send>(
            output_tx  : Sender<Message<OutputValue, OutputError>>)
   -> SourceWrap<OutputValue, OutputError>
 {
-  SourceWrap{ name: name, state: state, output_tx: output_tx }
+  SourceWrap{ name, state: restartable::new(state), output_tx }
+}
+
+impl<OutputValue: Send, OutputError: Send>
+    SourceWrap<OutputValue, OutputError>
+{
+  pub fn set_factory(&mut self,
+                     factory: SourceFactory<OutputValue, OutputError>)
+  {
+    self.state.set_factory(factory);
+  }
 }
 
 impl<OutputValue: Send, OutputError: Send> OutputCounter
@@ -37,8 +48,17 @@ impl<OutputValue: Send, OutputError: Send> Task
     for SourceWrap<OutputValue, OutputError>
 {
   fn execute(&mut self, stop: &mut bool) {
-    self.state.process(&mut self.output_tx, stop);
+    self.state.get().process(&mut self.output_tx, stop);
   }
+
+  fn shutdown(&mut self) {
+    self.state.get().shutdown();
+  }
+
+  fn restart(&mut self) -> bool {
+    self.state.restart()
+  }
+
   fn name(&self) -> &String { &self.name }
   fn input_count(&self) -> usize { 0 }
   fn output_count(&self) -> usize { 1 }
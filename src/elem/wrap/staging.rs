@@ -0,0 +1,120 @@
+use lossyq::spsc::{Sender, channel};
+use super::super::super::{ChannelWrapper, ChannelId, Message};
+use super::super::super::scheduler;
+use std::collections::{VecDeque};
+use std::mem;
+use std::cmp;
+
+// the capacity, the id of the input and the private channel
+type StagingChannel<Value, Error> =
+  (usize, ChannelId, Sender<Message<Value, Error>>, ChannelWrapper<Value, Error>);
+
+// enforces the max_messages budget on one input. while no more than
+// max_messages are waiting, the element reads the input itself. otherwise
+// the messages are taken out of the input channel and at most
+// max_messages of them are handed to the element through a private
+// channel, which is swapped in place of the input while the element
+// executes. the rest waits for the next execution.
+pub struct Staging<Value: Send, Error: Send> {
+  backlog     : VecDeque<Message<Value, Error>>,
+  channel     : Option<StagingChannel<Value, Error>>,
+  swapped     : bool,
+  // the position of the input when it was last read
+  read_seqno  : usize,
+}
+
+pub fn new<Value: Send, Error: Send>() -> Staging<Value, Error> {
+  Staging{ backlog: VecDeque::new(), channel: None, swapped: false, read_seqno: 0 }
+}
+
+impl<Value: Send, Error: Send> Staging<Value, Error> {
+  // the messages held back for the next execution and the ones sent to
+  // the input since it was last read
+  pub fn pending(&self, input: &ChannelWrapper<Value, Error>) -> usize {
+    self.backlog.len() + self.unread(input)
+  }
+
+  fn unread(&self, input: &ChannelWrapper<Value, Error>) -> usize {
+    match *input {
+      ChannelWrapper::ConnectedReceiver(_, ref receiver, _) => {
+        receiver.seqno().saturating_sub(self.read_seqno)
+      },
+      _ => 0,
+    }
+  }
+
+  // swaps the private channel in place of the input when more messages
+  // are waiting than the budget allows, or messages are held back from an
+  // earlier execution
+  pub fn enter(&mut self,
+               input: &mut ChannelWrapper<Value, Error>,
+               max_messages: Option<usize>)
+  {
+    let cap = match max_messages {
+      Some(max) => cmp::max(max, 1),
+      None if self.backlog.is_empty() => 0,
+      None => self.backlog.len(),
+    };
+    if let &mut ChannelWrapper::ConnectedReceiver(channel_id, ref mut receiver, ref sender_name) = input {
+      if self.backlog.is_empty() {
+        // taken before the element reads, so the messages that arrive
+        // meanwhile count as unread until the next execution
+        let seqno = receiver.seqno();
+        let unread = seqno.saturating_sub(self.read_seqno);
+        self.read_seqno = seqno;
+        if cap == 0 || unread <= cap {
+          return;
+        }
+        // the input is only read again when the backlog is used up, so
+        // the messages sent meanwhile are overwritten in the channel as
+        // usual and the backlog never holds more than one channel's worth
+        for msg in receiver.iter() {
+          self.backlog.push_back(msg);
+        }
+      }
+      let recreate = match self.channel {
+        Some((size, id, _, _)) => size < cap || id != channel_id,
+        None => true,
+      };
+      if recreate {
+        let (tx, rx) = channel(cap);
+        self.channel = Some((cap, channel_id, tx,
+                             ChannelWrapper::ConnectedReceiver(channel_id, rx, sender_name.clone())));
+      }
+    } else {
+      return;
+    }
+    if let Some((_, _, ref mut tx, ref mut staged)) = self.channel {
+      for _ in 0..cap {
+        match self.backlog.pop_front() {
+          Some(msg) => {
+            let mut msg = Some(msg);
+            tx.put(|v| *v = msg.take());
+          },
+          None => { break; }
+        }
+      }
+      mem::swap(input, staged);
+      self.swapped = true;
+    }
+  }
+
+  // puts the input back and runs the task again if messages are waiting
+  // beyond the budget
+  pub fn leave(&mut self,
+               input: &mut ChannelWrapper<Value, Error>)
+  {
+    let capped = self.swapped;
+    if self.swapped {
+      if let Some((_, _, _, ref mut staged)) = self.channel {
+        mem::swap(input, staged);
+      }
+      self.swapped = false;
+    }
+    if !self.backlog.is_empty() || (capped && self.unread(input) > 0) {
+      if let Some(waker) = scheduler::task_waker() {
+        waker.wake();
+      }
+    }
+  }
+}
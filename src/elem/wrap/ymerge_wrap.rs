@@ -5,18 +5,23 @@ use super::super::super::{Task, Message, ChannelWrapper, ChannelId, SenderName,
 use super::super::connectable::{ConnectableY};
 use super::super::identified_input::{IdentifiedInput};
 use super::super::counter::{OutputCounter, InputCounter};
-use super::super::ymerge::{YMerge};
+use super::super::ymerge::{YMerge, YMergeFactory};
+use super::restartable::{self, Restartable};
+use super::super::super::scheduler;
+use super::staging::{self, Staging};
 
 pub struct YMergeWrap<InputValueA: Send, InputErrorA: Send,
                       InputValueB: Send, InputErrorB: Send,
                       OutputValue: Send, OutputError: Send>
 {
   name         : String,
-  state        : Box<YMerge<InputValueA=InputValueA, InputErrorA=InputErrorA,
-                            InputValueB=InputValueB, InputErrorB=InputErrorB,
-                            OutputValue=OutputValue, OutputError=OutputError>+Send>,
+  state        : Restartable<dyn YMerge<InputValueA=InputValueA, InputErrorA=InputErrorA,
+                                        InputValueB=InputValueB, InputErrorB=InputErrorB,
+                                        OutputValue=OutputValue, OutputError=OutputError>+Send>,
   input_a_rx   : ChannelWrapper<InputValueA, InputErrorA>,
   input_b_rx   : ChannelWrapper<InputValueB, InputErrorB>,
+  input_a_staging : Staging<InputValueA, InputErrorA>,
+  input_b_staging : Staging<InputValueB, InputErrorB>,
   output_tx    : Sender<Message<OutputValue, OutputError>>,
 }
 
@@ -34,13 +39,31 @@ pub fn new<InputValueA: Send, InputErrorA: Send,
 {
   YMergeWrap{
     name: name,
-    state: state,
+    state: restartable::new(state),
     input_a_rx: input_a_rx,
     input_b_rx: input_b_rx,
+    input_a_staging: staging::new(),
+    input_b_staging: staging::new(),
     output_tx: output_tx
   }
 }
 
+impl<InputValueA: Send, InputErrorA: Send,
+     InputValueB: Send, InputErrorB: Send,
+     OutputValue: Send, OutputError: Send>
+    YMergeWrap<InputValueA, InputErrorA,
+                   InputValueB, InputErrorB,
+                   OutputValue, OutputError>
+{
+  pub fn set_factory(&mut self,
+                     factory: YMergeFactory<InputValueA, InputErrorA,
+                                            InputValueB, InputErrorB,
+                                            OutputValue, OutputError>)
+  {
+    self.state.set_factory(factory);
+  }
+}
+
 impl<InputValueA: Send, InputErrorA: Send,
      InputValueB: Send, InputErrorB: Send,
      OutputValue: Send, OutputError: Send> IdentifiedInput
@@ -140,11 +163,29 @@ impl<InputValueA: Send, InputErrorA: Send,
                    OutputValue, OutputError>
 {
   fn execute(&mut self, stop: &mut bool) {
-    self.state.process(&mut self.input_a_rx,
-                       &mut self.input_b_rx,
-                       &mut self.output_tx,
-                       stop);
+    let max_messages = scheduler::budget().max_messages;
+    self.input_a_staging.enter(&mut self.input_a_rx, max_messages);
+    self.input_b_staging.enter(&mut self.input_b_rx, max_messages);
+    self.state.get().process(&mut self.input_a_rx,
+                             &mut self.input_b_rx,
+                             &mut self.output_tx,
+                             stop);
+    self.input_a_staging.leave(&mut self.input_a_rx);
+    self.input_b_staging.leave(&mut self.input_b_rx);
   }
+
+  fn shutdown(&mut self) {
+    self.state.get().shutdown();
+  }
+
+  fn restart(&mut self) -> bool {
+    self.state.restart()
+  }
+
+  fn pending_messages(&self) -> usize {
+    self.input_a_staging.pending(&self.input_a_rx) + self.input_b_staging.pending(&self.input_b_rx)
+  }
+
   fn name(&self) -> &String { &self.name }
   fn input_count(&self) -> usize { 2 }
   fn output_count(&self) -> usize { 1 }
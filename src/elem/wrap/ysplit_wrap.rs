@@ -5,16 +5,20 @@ use super::super::super::{Task, Message, ChannelWrapper, ChannelId,
 use super::super::connectable::{Connectable};
 use super::super::identified_input::{IdentifiedInput};
 use super::super::counter::{OutputCounter, InputCounter};
-use super::super::ysplit::{YSplit};
+use super::super::ysplit::{YSplit, YSplitFactory};
+use super::restartable::{self, Restartable};
+use super::super::super::scheduler;
+use super::staging::{self, Staging};
 
 pub struct YSplitWrap<InputValue: Send,   InputError: Send,
                       OutputValueA: Send, OutputErrorA: Send,
                       OutputValueB: Send, OutputErrorB: Send> {
   name          : String,
-  state         : Box<YSplit<InputValue=InputValue, InputError=InputError,
-                             OutputValueA=OutputValueA, OutputErrorA=OutputErrorA,
-                             OutputValueB=OutputValueB, OutputErrorB=OutputErrorB>+Send>,
+  state         : Restartable<dyn YSplit<InputValue=InputValue, InputError=InputError,
+                                         OutputValueA=OutputValueA, OutputErrorA=OutputErrorA,
+                                         OutputValueB=OutputValueB, OutputErrorB=OutputErrorB>+Send>,
   input_rx      : ChannelWrapper<InputValue, InputError>,
+  input_staging : Staging<InputValue, InputError>,
   output_a_tx   : Sender<Message<OutputValueA, OutputErrorA>>,
   output_b_tx   : Sender<Message<OutputValueB, OutputErrorB>>,
 }
@@ -33,13 +37,30 @@ pub fn new<InputValue: Send,   InputError: Send,
 {
   YSplitWrap{
     name: name,
-    state: state,
+    state: restartable::new(state),
     input_rx: input_rx,
+    input_staging: staging::new(),
     output_a_tx: output_a_tx,
     output_b_tx: output_b_tx
   }
 }
 
+impl<InputValue: Send,   InputError: Send,
+     OutputValueA: Send, OutputErrorA: Send,
+     OutputValueB: Send, OutputErrorB: Send>
+    YSplitWrap<InputValue, InputError,
+                   OutputValueA, OutputErrorA,
+                   OutputValueB, OutputErrorB>
+{
+  pub fn set_factory(&mut self,
+                     factory: YSplitFactory<InputValue, InputError,
+                                            OutputValueA, OutputErrorA,
+                                            OutputValueB, OutputErrorB>)
+  {
+    self.state.set_factory(factory);
+  }
+}
+
 impl<InputValue: Send,   InputError: Send,
      OutputValueA: Send, OutputErrorA: Send,
      OutputValueB: Send, OutputErrorB: Send> IdentifiedInput
@@ -122,10 +143,25 @@ impl<InputValue: Send,   InputError: Send,
                    OutputValueB, OutputErrorB>
 {
   fn execute(&mut self, stop: &mut bool) {
-    self.state.process(&mut self.input_rx,
-                       &mut self.output_a_tx,
-                       &mut self.output_b_tx,
-                       stop);
+    let max_messages = scheduler::budget().max_messages;
+    self.input_staging.enter(&mut self.input_rx, max_messages);
+    self.state.get().process(&mut self.input_rx,
+                             &mut self.output_a_tx,
+                             &mut self.output_b_tx,
+                             stop);
+    self.input_staging.leave(&mut self.input_rx);
+  }
+
+  fn shutdown(&mut self) {
+    self.state.get().shutdown();
+  }
+
+  fn restart(&mut self) -> bool {
+    self.state.restart()
+  }
+
+  fn pending_messages(&self) -> usize {
+    self.input_staging.pending(&self.input_rx)
   }
 
   fn name(&self) -> &String { &self.name }
@@ -18,8 +18,19 @@ pub trait YMerge {
     input_b:  &mut ChannelWrapper<Self::InputValueB, Self::InputErrorB>,
     output:   &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
     stop:     &mut bool);
+
+  // called once when the scheduler shuts down
+  fn shutdown(&mut self) {}
 }
 
+// creates a fresh element when the task is restarted by the scheduler
+pub type YMergeFactory<InputValueA, InputErrorA,
+                       InputValueB, InputErrorB,
+                       OutputValue, OutputError> =
+  Box<dyn FnMut() -> Box<dyn YMerge<InputValueA=InputValueA, InputErrorA=InputErrorA,
+                                    InputValueB=InputValueB, InputErrorB=InputErrorB,
+                                    OutputValue=OutputValue, OutputError=OutputError>+Send>+Send>;
+
 pub fn new<InputValueA: Send, InputErrorA: Send,
            InputValueB: Send, InputErrorB: Send,
            OutputValue: Send, OutputError: Send>(
@@ -18,8 +18,19 @@ pub trait YSplit {
     output_a:  &mut Sender<Message<Self::OutputValueA, Self::OutputErrorA>>,
     output_b:  &mut Sender<Message<Self::OutputValueB, Self::OutputErrorB>>,
     stop:      &mut bool);
+
+  // called once when the scheduler shuts down
+  fn shutdown(&mut self) {}
 }
 
+// creates a fresh element when the task is restarted by the scheduler
+pub type YSplitFactory<InputValue, InputError,
+                       OutputValueA, OutputErrorA,
+                       OutputValueB, OutputErrorB> =
+  Box<dyn FnMut() -> Box<dyn YSplit<InputValue=InputValue, InputError=InputError,
+                                    OutputValueA=OutputValueA, OutputErrorA=OutputErrorA,
+                                    OutputValueB=OutputValueB, OutputErrorB=OutputErrorB>+Send>+Send>;
+
 pub fn new<InputValue: Send,   InputError: Send,
            OutputValueA: Send, OutputErrorA: Send,
            OutputValueB: Send, OutputErrorB: Send>(
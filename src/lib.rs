@@ -1,6 +1,9 @@
 extern crate lossyq;
 extern crate libc;
 
+use std::time::{Instant};
+use std::os::unix::io::{RawFd};
+
 pub mod scheduler;
 pub mod elem;
 
@@ -29,7 +32,9 @@ pub enum Error {
   NonExistent,
   Stopping,
   AlreadyExists,
-  InvalidChannelState(ExpectedChannelState, ActualChannelState)
+  InvalidChannelState(ExpectedChannelState, ActualChannelState),
+  // the errno of a failed system call
+  OsError(i32),
 }
 
 #[derive(Copy,Clone,Debug,PartialEq,Eq)]
@@ -69,6 +74,164 @@ pub enum SchedulingRule {
   OnMessage,
   Periodic(PeriodLengthInUsec),
   OnExternalEvent,
+  // like OnMessage and OnExternalEvent, but the task is also executed
+  // when nothing happened for the given time
+  OnMessageOrTimeout(PeriodLengthInUsec),
+  OnExternalEventOrTimeout(PeriodLengthInUsec),
+  // executed once after the delay or at the given time
+  After(PeriodLengthInUsec),
+  At(Instant),
+  // periodically on a fixed grid from the start of the scheduler. the
+  // ticks missed because of late execution are skipped and reported by
+  // scheduler::skipped_ticks()
+  PeriodicAligned(PeriodLengthInUsec),
+  // when the file descriptor becomes readable or writable. the task is
+  // not triggered again until it was executed.
+  OnReadable(RawFd),
+  OnWritable(RawFd),
+  // when the signal arrives, through a signalfd
+  OnSignal(i32),
+  // on the blocking thread pool: when a message arrives, or continuously
+  // for tasks without inputs
+  Blocking,
+}
+
+// what triggers the Periodic tasks
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum PeriodicTimer {
+  // the executors compare the period to the ticker's time (default)
+  Ticker,
+  // a timerfd per task, Linux only
+  TimerFd,
+}
+
+// why the scheduler executes the task, see scheduler::wake_reason()
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum WakeReason {
+  // a message or a notification arrived
+  Triggered,
+  // the timeout of the conditional rule expired
+  Timeout,
+  // Loop and Periodic tasks
+  Scheduled,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct RestartIntensity {
+  pub max_restarts:  usize,
+  pub window:        PeriodLengthInUsec,
+  pub backoff:       PeriodLengthInUsec,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum RestartPolicy {
+  Never,
+  Always,
+  OnFailure(RestartIntensity),
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum SupervisorStrategy {
+  OneForOne,
+  OneForAll,
+}
+
+// what the watchdog does besides reporting a task stuck in process
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum WatchdogAction {
+  Report,
+  // the task is marked failed when process returns, so its supervisor
+  // can restart it
+  MarkFailed,
+  // aborts the process
+  Abort,
+}
+
+// an execution that exceeded the watchdog's threshold
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct StuckTask {
+  pub id:           TaskId,
+  pub name:         String,
+  pub executor:     usize,
+  pub thread_name:  Option<String>,
+  // the time since the execution started when it was detected
+  pub running_us:   usize,
+}
+
+// the limits of one execution. the built-in wrappers hand at most
+// max_messages messages of each input to the element and run the task
+// again right away if more is waiting. max_ns is advisory, elements check
+// it with scheduler::budget_expired().
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Default)]
+pub struct ExecBudget {
+  pub max_messages:  Option<usize>,
+  pub max_ns:        Option<u64>,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct IdleSpinCount (pub usize);
+
+// what the executor threads do when a pass over the tasks found nothing
+// to run. the spin count is the number of idle passes before yielding
+// or parking.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum IdleStrategy {
+  Spin,
+  SpinThenYield(IdleSpinCount),
+  SpinThenPark(IdleSpinCount),
+}
+
+// the realtime scheduling policy of a thread with its priority. Default
+// leaves the thread with the OS default policy.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum ThreadPriority {
+  Default,
+  Fifo(i32),
+  RoundRobin(i32),
+}
+
+// the settings of a scheduler thread. an empty cpu list means the thread
+// can run on any CPU.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct ThreadConfig {
+  pub name:      Option<String>,
+  pub cpus:      Vec<usize>,
+  pub priority:  ThreadPriority,
+  // executor threads only: the thread group tasks can be pinned to
+  pub group:     Option<String>,
+}
+
+impl ThreadConfig {
+  pub fn new() -> ThreadConfig {
+    ThreadConfig{
+      name:      None,
+      cpus:      Vec::new(),
+      priority:  ThreadPriority::Default,
+      group:     None,
+    }
+  }
+}
+
+impl Default for ThreadConfig {
+  fn default() -> ThreadConfig {
+    ThreadConfig::new()
+  }
+}
+
+// ready tasks with higher priority are executed first
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Priority {
+  High,
+  Normal,
+  Low,
+}
+
+// the executor threads allowed to run a task
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum TaskAffinity {
+  Any,
+  Executor(usize),
+  Group(String),
 }
 
 #[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
@@ -83,6 +246,14 @@ pub struct ReceiverName (pub String);
 
 pub trait Task {
   fn execute(&mut self, stop: &mut bool);
+  // called by the supervisor before a stopped or failed task is executed
+  // again. returns true if the task recreated its internal state.
+  fn restart(&mut self) -> bool { false }
+  // called once by Scheduler::shutdown(..) after the executors stopped
+  fn shutdown(&mut self) {}
+  // the messages waiting in the inputs and the ones taken from them but
+  // not yet handed to the element
+  fn pending_messages(&self) -> usize { 0 }
   fn name(&self) -> &String;
   fn input_count(&self) -> usize;
   fn output_count(&self) -> usize;
@@ -91,6 +262,106 @@ pub trait Task {
   fn output_channel_pos(&self, ch_id: SenderChannelId) -> ChannelPosition;
 }
 
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum TaskState {
+  Running,
+  Paused,
+  // stopped by the element or by the shutdown
+  Stopped,
+  Failed,
+}
+
+// a task in the scheduler's registry
+#[derive(Clone,Debug)]
+pub struct TaskInfo {
+  pub name:     String,
+  pub id:       TaskId,
+  pub rule:     SchedulingRule,
+  pub state:    TaskState,
+  // the connected inputs with the sending task's name. its id is None
+  // until a task with that name is added.
+  pub inputs:   Vec<(ChannelId, SenderName, Option<TaskId>)>,
+  // the tasks receiving from the outputs
+  pub outputs:  Vec<(ChannelId, TaskId)>,
+}
+
+#[derive(Clone,Debug)]
+pub struct TaskStats {
+  pub name:              String,
+  pub id:                TaskId,
+  pub rule:              SchedulingRule,
+  pub exec_count:        u64,
+  pub total_exec_ns:     u64,
+  pub max_exec_ns:       u64,
+  pub last_run:          Option<Instant>,
+  pub skipped_ticks:     u64,
+  pub input_positions:   Vec<ChannelPosition>,
+  pub output_positions:  Vec<ChannelPosition>,
+  // the time spent in process
+  pub exec_latency:      LatencyPercentiles,
+  // the time from becoming ready (notified, triggered or due) until the
+  // execution started. measured by the ticker's clock, so its resolution
+  // is about 10 usec.
+  pub delay_latency:     LatencyPercentiles,
+}
+
+// in nanoseconds, from a log-bucketed histogram
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Default)]
+pub struct LatencyPercentiles {
+  pub count:  u64,
+  pub p50:    u64,
+  pub p99:    u64,
+  pub p999:   u64,
+  pub max:    u64,
+}
+
+#[derive(Clone,Debug)]
+pub struct ExecutorStats {
+  pub id:                usize,
+  pub loop_count:        u64,
+  pub busy_loop_count:   u64,
+  pub busy_ns:           u64,
+  pub elapsed_ns:        u64,
+}
+
+impl ExecutorStats {
+  // the ratio of the time spent in task execution
+  pub fn busy_ratio(&self) -> f64 {
+    if self.elapsed_ns == 0 {
+      0.0
+    } else {
+      self.busy_ns as f64 / self.elapsed_ns as f64
+    }
+  }
+}
+
+#[derive(Clone,Debug)]
+pub struct SchedulerStats {
+  pub tasks:      Vec<TaskStats>,
+  pub executors:  Vec<ExecutorStats>,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct PollResult {
+  // the number of executed tasks. when non-zero, other tasks may have
+  // become ready meanwhile.
+  pub executed:    usize,
+  // the time until the next timed task is due, if any
+  pub next_timer:  Option<PeriodLengthInUsec>,
+}
+
+#[derive(Clone,Debug)]
+pub struct ShutdownReport {
+  // the tasks with inputs that processed all their messages
+  pub drained:    Vec<(TaskId, String)>,
+  // the tasks that still had input when the timeout expired, with the
+  // number of their pending messages
+  pub dropped:    Vec<(TaskId, String, usize)>,
+  // the tasks without inputs that were stopped first
+  pub sources:    Vec<(TaskId, String)>,
+  pub timed_out:  bool,
+}
+
 pub enum ChannelWrapper<Value: Send, Error: Send> {
   ReceiverNotConnected(ReceiverChannelId, ReceiverName),
   ConnectedReceiver(ChannelId, Receiver<Message<Value, Error>>, SenderName),
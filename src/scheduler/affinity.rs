@@ -0,0 +1,48 @@
+use super::super::{Error, ThreadConfig, ThreadPriority};
+use libc;
+
+// applies the CPU set and the priority to the calling thread
+#[cfg(target_os = "linux")]
+pub fn apply(config: &ThreadConfig) -> Result<(), Error> {
+  use std::mem;
+
+  unsafe {
+    if !config.cpus.is_empty() {
+      let mut set : libc::cpu_set_t = mem::zeroed();
+      libc::CPU_ZERO(&mut set);
+      for cpu in config.cpus.iter() {
+        if *cpu >= 8*mem::size_of::<libc::cpu_set_t>() {
+          return Result::Err(Error::OsError(libc::EINVAL));
+        }
+        libc::CPU_SET(*cpu, &mut set);
+      }
+      let rc = libc::pthread_setaffinity_np(libc::pthread_self(),
+                                            mem::size_of::<libc::cpu_set_t>(),
+                                            &set);
+      if rc != 0 {
+        return Result::Err(Error::OsError(rc));
+      }
+    }
+
+    let (policy, priority) = match config.priority {
+      ThreadPriority::Default              => { return Ok(()); },
+      ThreadPriority::Fifo(priority)       => (libc::SCHED_FIFO, priority),
+      ThreadPriority::RoundRobin(priority) => (libc::SCHED_RR, priority),
+    };
+    let param = libc::sched_param{ sched_priority: priority };
+    let rc = libc::pthread_setschedparam(libc::pthread_self(), policy, &param);
+    if rc != 0 {
+      return Result::Err(Error::OsError(rc));
+    }
+  }
+  Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply(config: &ThreadConfig) -> Result<(), Error> {
+  if config.cpus.is_empty() && config.priority == ThreadPriority::Default {
+    Ok(())
+  } else {
+    Result::Err(Error::OsError(libc::ENOSYS))
+  }
+}
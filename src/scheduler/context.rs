@@ -0,0 +1,95 @@
+use std::cell::{Cell, RefCell};
+use std::task::{Waker};
+use std::time::{Instant};
+use std::mem;
+use super::super::{WakeReason, TaskId, ExecBudget, ChannelId};
+use super::handle::{SchedulerDataHandle};
+use super::waker;
+
+// the state of the task that the current executor thread runs
+thread_local! {
+  static WAKE_REASON: Cell<WakeReason> = const { Cell::new(WakeReason::Scheduled) };
+  static SKIPPED_TICKS: Cell<usize> = const { Cell::new(0) };
+  static TASK_ID: Cell<Option<TaskId>> = const { Cell::new(None) };
+  static BUDGET: Cell<(ExecBudget, Option<Instant>)> = Cell::new((ExecBudget::default(), None));
+  // the scheduler that the current thread executes tasks for
+  static SCHEDULER: RefCell<Option<SchedulerDataHandle>> = const { RefCell::new(None) };
+  // the dependents of the current task that were added while it runs
+  static DEPENDENTS: RefCell<Vec<(ChannelId, TaskId)>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn set_wake_reason(reason: WakeReason) {
+  WAKE_REASON.with(|r| r.set(reason));
+}
+
+pub fn wake_reason() -> WakeReason {
+  WAKE_REASON.with(|r| r.get())
+}
+
+pub fn set_skipped_ticks(ticks: usize) {
+  SKIPPED_TICKS.with(|t| t.set(ticks));
+}
+
+pub fn skipped_ticks() -> usize {
+  SKIPPED_TICKS.with(|t| t.get())
+}
+
+pub fn set_task(id: TaskId) {
+  TASK_ID.with(|t| t.set(Some(id)));
+}
+
+pub fn set_budget(budget: ExecBudget, started: Instant) {
+  BUDGET.with(|b| b.set((budget, Some(started))));
+}
+
+pub fn budget() -> ExecBudget {
+  BUDGET.with(|b| b.get().0)
+}
+
+pub fn budget_expired() -> bool {
+  match BUDGET.with(|b| b.get()) {
+    (ExecBudget{ max_ns: Some(max_ns), .. }, Some(started)) => {
+      let elapsed = started.elapsed();
+      elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64 >= max_ns
+    },
+    _ => false,
+  }
+}
+
+pub fn clear_task() {
+  TASK_ID.with(|t| t.set(None));
+}
+
+pub fn current_task() -> Option<TaskId> {
+  TASK_ID.with(|t| t.get())
+}
+
+// the slot of the running task is empty, so the executor registers these
+// after the execution
+pub fn defer_dependents(mut deps: Vec<(ChannelId, TaskId)>) {
+  DEPENDENTS.with(|d| d.borrow_mut().append(&mut deps));
+}
+
+pub fn take_dependents() -> Vec<(ChannelId, TaskId)> {
+  DEPENDENTS.with(|d| mem::take(&mut *d.borrow_mut()))
+}
+
+pub fn set_scheduler(handle: SchedulerDataHandle) {
+  SCHEDULER.with(|s| *s.borrow_mut() = Some(handle));
+}
+
+pub fn scheduler() -> Option<SchedulerDataHandle> {
+  SCHEDULER.with(|s| {
+    (*s.borrow()).as_ref().map(|handle| handle.clone())
+  })
+}
+
+pub fn task_waker() -> Option<Waker> {
+  let id = match TASK_ID.with(|t| t.get()) {
+    Some(id) => id,
+    None     => { return None; }
+  };
+  SCHEDULER.with(|s| {
+    (*s.borrow()).as_ref().map(|handle| waker::task_waker(handle.clone(), id))
+  })
+}
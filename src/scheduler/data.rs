@@ -2,37 +2,79 @@
 use std::collections::{HashMap};
 use std::sync::atomic::{AtomicUsize, AtomicBool, AtomicPtr, Ordering};
 use super::super::{Task, Error, TaskId, ReceiverChannelId,
-  ChannelId, SchedulingRule, PeriodLengthInUsec};
-use super::{page, prv};
-use std::sync::{Mutex};
+  ChannelId, SenderName, SchedulingRule, PeriodLengthInUsec, IdleStrategy,
+  RestartPolicy, SupervisorStrategy, SchedulerStats, TaskAffinity, Priority,
+  PeriodicTimer, SenderChannelId, WatchdogAction, StuckTask, ExecBudget,
+  TaskInfo, TaskState};
+use super::{page, prv, idle, supervisor, stats, poller, trace, watchdog, context, wrap};
+use super::waker::{OutputWaker};
+use std::sync::{Mutex, Arc};
 use std::ptr;
-use std::time::{Instant};
+use std::mem;
+use std::cmp;
+use std::thread;
+use std::time::{Instant, Duration};
 use libc;
 
+// the ID and name of the tasks
+type TaskList = Vec<(TaskId, String)>;
+
 pub struct SchedulerData {
-  // ticker only:
+  // ticker and parked executors only:
   start:       Instant,
   // shared between threads
   // everything below has to be thread safe:
   max_id:      AtomicUsize,
   l1:          Vec<AtomicPtr<page::TaskPage>>,
   stop:        AtomicBool,
+  // the time is only advanced by advance_time(..)
+  virtual_time:  AtomicBool,
+  paused:      AtomicBool,
   time_us:     AtomicUsize,
   ids:         Mutex<HashMap<String, TaskId>>,
+  // the statistics of the tasks, read without waiting for them
+  counters:    Mutex<HashMap<TaskId, Arc<stats::TaskCounters>>>,
   unresolved:  Mutex<HashMap<String, HashMap<TaskId,Vec<ChannelId>>>>,
+  idle:        idle::Idle,
+  failures:    Mutex<Vec<(TaskId, String)>>,
+  supervisor:  supervisor::Supervisor,
+  executors:   Mutex<Vec<Arc<stats::ExecutorCounters>>>,
+  groups:      Mutex<Vec<String>>,
+  // the number of tasks on each priority level
+  priority_counts:   Vec<AtomicUsize>,
+  starvation_limit:  AtomicUsize,
+  poller:      poller::Poller,
+  periodic_timer:  Mutex<PeriodicTimer>,
+  // the number of Blocking tasks, the threads of the blocking pool, and
+  // whether the pool was started with the executors
+  blocking:    Mutex<(usize, usize, bool)>,
+  max_blocking_threads:  AtomicUsize,
+  // bumped when tracing is started or stopped. the buffers are kept
+  // for the export after tracing stopped.
+  trace_generation:  AtomicUsize,
+  traces:      Mutex<(usize, Vec<Arc<trace::TraceBuffer>>)>,
+  watchdog:    watchdog::Watchdog,
+  watchdog_enabled:  AtomicBool,
+  // the budget of the tasks without their own. bumped when it is set,
+  // so the executors pick it up.
+  budget:      Mutex<ExecBudget>,
+  budget_generation:  AtomicUsize,
+  // the tasks can be added from several threads through the handles
+  adding:      Arc<Mutex<()>>,
+  // the poller and the blocking pool, started on demand
+  threads:     Mutex<Vec<thread::JoinHandle<()>>>,
+  // used by step() on the calling thread only
+  step_skipped_passes:  Mutex<Vec<usize>>,
 }
 
 impl SchedulerData {
-  fn add_l2_page(&mut self, idx: usize) {
-    let array = Box::new(page::new(idx));
-    let len = self.l1.len();
-    if idx >= len-1 {
-      // extend slice
-      for _i in 0..initial_capacity() {
-        self.l1.push(AtomicPtr::default());
-      }
+  // the l1 bucket is filled once, it has room for 4 billion task ids
+  fn add_l2_page(&self, idx: usize) {
+    if idx >= self.l1.len() {
+      return;
     }
-    let l1_slice = self.l1.as_mut_slice();
+    let array = Box::new(page::new(idx));
+    let l1_slice = self.l1.as_slice();
     l1_slice[idx].store(Box::into_raw(array), Ordering::Release);
   }
 
@@ -44,9 +86,32 @@ impl SchedulerData {
       max_id:      AtomicUsize::new(1),
       l1:          Vec::with_capacity(l1_size),
       stop:        AtomicBool::new(false),
+      virtual_time:  AtomicBool::new(false),
+      paused:      AtomicBool::new(false),
       time_us:     AtomicUsize::new(0),
       ids:         Mutex::new(HashMap::new()),
+      counters:    Mutex::new(HashMap::new()),
       unresolved:  Mutex::new(HashMap::new()),
+      idle:        idle::new(),
+      failures:    Mutex::new(Vec::new()),
+      supervisor:  supervisor::new(),
+      executors:   Mutex::new(Vec::new()),
+      groups:      Mutex::new(Vec::new()),
+      priority_counts:   (0..page::priority_levels()).map(|_| AtomicUsize::new(0)).collect(),
+      starvation_limit:  AtomicUsize::new(100),
+      step_skipped_passes:  Mutex::new(vec![0usize; page::priority_levels()]),
+      poller:      poller::new(),
+      periodic_timer:  Mutex::new(PeriodicTimer::Ticker),
+      blocking:    Mutex::new((0, 0, false)),
+      max_blocking_threads:  AtomicUsize::new(16),
+      trace_generation:  AtomicUsize::new(0),
+      traces:      Mutex::new((0, Vec::new())),
+      watchdog:    watchdog::new(),
+      watchdog_enabled:  AtomicBool::new(false),
+      budget:      Mutex::new(ExecBudget::default()),
+      budget_generation:  AtomicUsize::new(0),
+      adding:      Arc::new(Mutex::new(())),
+      threads:     Mutex::new(Vec::new()),
     };
 
     // fill the l1 bucket
@@ -60,32 +125,33 @@ impl SchedulerData {
     data
   }
 
-  fn mark_conditional_task(&mut self,
-                           id: TaskId)
+  fn mark_conditional_task(&self,
+                           id: TaskId,
+                           timeout: PeriodLengthInUsec)
   {
     let (l1, l2) = page::position(id.0);
     unsafe {
-      let l1_ptr = self.l1.get_unchecked_mut(l1).load(Ordering::Acquire);
+      let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
       if l1_ptr.is_null() == false {
-        (*l1_ptr).set_conditional_exec_flag(l2);
+        (*l1_ptr).set_conditional_exec_flag(l2, timeout);
       }
     }
   }
 
-  fn mark_periodic_task(&mut self,
+  fn mark_periodic_task(&self,
                         id: TaskId,
                         period: PeriodLengthInUsec)
   {
     let (l1, l2) = page::position(id.0);
     unsafe {
-      let l1_ptr = self.l1.get_unchecked_mut(l1).load(Ordering::Acquire);
+      let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
       if l1_ptr.is_null() == false {
         (*l1_ptr).set_delayed_exec(l2, period);
       }
     }
   }
 
-  fn allocate_id_for_task(&mut self, task: &Box<Task+Send>) -> Result<TaskId, Error> {
+  fn allocate_id_for_task(&self, task: &Box<dyn Task+Send>) -> Result<TaskId, Error> {
     let mut ids = self.ids.lock().unwrap();
     if ids.contains_key(task.name()) {
       Result::Err(Error::AlreadyExists)
@@ -96,6 +162,16 @@ impl SchedulerData {
     }
   }
 
+  fn resolve_task_name(&self, id: TaskId) -> Option<String> {
+    let ids = self.ids.lock().unwrap();
+    for (name, task_id) in ids.iter() {
+      if *task_id == id {
+        return Some(name.clone());
+      }
+    }
+    None
+  }
+
   fn resolve_task_id(&self, name: &String) -> Option<TaskId> {
     let ids = self.ids.lock().unwrap();
     match ids.get(name) {
@@ -104,27 +180,209 @@ impl SchedulerData {
     }
   }
 
-  fn register_dependents(&mut self,
+  // fails if the sender was removed meanwhile
+  fn register_dependents(&self,
                          id: TaskId,
                          deps: Vec<(ChannelId, TaskId)>)
+      -> Result<(), Error>
   {
-    if deps.is_empty() { return; }
+    if deps.is_empty() { return Ok(()); }
+    if context::current_task() == Some(id) {
+      // added by the task itself, which holds its slot
+      context::defer_dependents(deps);
+      return Ok(());
+    }
     let (l1, l2) = page::position(id.0);
     unsafe {
-      let l1_ptr = self.l1.get_unchecked_mut(l1).load(Ordering::Acquire);
+      let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
       if l1_ptr.is_null() == false {
         (*l1_ptr).set_dependents_flag(l2);
-        (*l1_ptr).register_dependents(l2, deps);
+        return (*l1_ptr).register_dependents(l2, deps);
+      }
+    }
+    Ok(())
+  }
+
+  // wakes the async reader after the sender's output channel advanced
+  pub fn add_output_waker(&self,
+                          sender_name: &String,
+                          ch_id: SenderChannelId,
+                          waker: Arc<OutputWaker>)
+      -> Result<(), Error>
+  {
+    let id = match self.resolve_task_id(sender_name) {
+      Some(id) => id,
+      None     => { return Result::Err(Error::NonExistent); }
+    };
+    let (l1, l2) = page::position(id.0);
+    unsafe {
+      let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
+      if l1_ptr.is_null() {
+        return Result::Err(Error::NonExistent);
       }
+      (*l1_ptr).set_dependents_flag(l2);
+      (*l1_ptr).add_output_waker(l2, ch_id, waker)
     }
   }
 
-  pub fn add_task(&mut self,
+  fn group_id(&self, name: &String) -> usize {
+    let mut groups = self.groups.lock().unwrap();
+    match groups.iter().position(|g| g == name) {
+      Some(pos) => pos,
+      None      => {
+        groups.push(name.clone());
+        groups.len()-1
+      }
+    }
+  }
+
+  fn affinity_key(&self, affinity: &TaskAffinity) -> usize {
+    match *affinity {
+      TaskAffinity::Any                => 0,
+      TaskAffinity::Executor(executor) => page::executor_affinity(executor),
+      TaskAffinity::Group(ref name)    => page::group_affinity(self.group_id(name)),
+    }
+  }
+
+  // registers the descriptor that triggers the task, if the rule needs
+  // one. returns true if the task is triggered by the poller thread.
+  fn watch(&self,
+           id: TaskId,
+           rule: SchedulingRule)
+      -> Result<bool, Error>
+  {
+    let result = match rule {
+      SchedulingRule::OnReadable(fd) => self.poller.register(id, fd, poller::Interest::Readable),
+      SchedulingRule::OnWritable(fd) => self.poller.register(id, fd, poller::Interest::Writable),
+      SchedulingRule::OnSignal(signum) => self.poller.register_signal(id, signum),
+      SchedulingRule::Periodic(period) if *self.periodic_timer.lock().unwrap() == PeriodicTimer::TimerFd => {
+        self.poller.register_timer(id, period)
+      },
+      _ => { return Ok(false); }
+    };
+    result.map(|_| true)
+  }
+
+  pub fn set_periodic_timer(&self, timer: PeriodicTimer) {
+    *self.periodic_timer.lock().unwrap() = timer;
+  }
+
+  // when a task with the rule is first executed. zero means right away.
+  fn first_exec_at(&self, rule: SchedulingRule) -> usize {
+    // the ticker may be parked, so the time can be old
+    self.update_time();
+    let now = self.time_us.load(Ordering::Acquire);
+    match rule {
+      SchedulingRule::After(delay) => now + delay.0,
+      SchedulingRule::At(at) => {
+        let diff = at.saturating_duration_since(self.start);
+        diff.as_secs() as usize * 1_000_000 + diff.subsec_nanos() as usize / 1000
+      },
+      SchedulingRule::PeriodicAligned(period) => page::grid_point(now, period),
+      _ => 0,
+    }
+  }
+
+  // the senders of the inputs will trigger the task
+  fn register_inputs(&self,
+                     task_id: TaskId,
+                     inputs: Vec<(ChannelId, SenderName)>)
+  {
+    for (sender_ch_id, sender_name) in inputs {
+      self.connect_to_sender(&sender_name.0, vec![(sender_ch_id, task_id)]);
+    }
+  }
+
+  // registers the dependents with the sender, or records them until a
+  // sender with that name is added. the sender is looked up while no
+  // task is added or removed, but it is waited for without the lock,
+  // because a running sender may add tasks too.
+  fn connect_to_sender(&self,
+                       sender_name: &String,
+                       deps: Vec<(ChannelId, TaskId)>)
+  {
+    if deps.is_empty() { return; }
+    loop {
+      let sender_id = {
+        let adding = self.adding.clone();
+        let _adding = adding.lock().unwrap();
+        let sender_id = self.resolve_task_id(sender_name);
+        if sender_id.is_none() {
+          // the sender is not added yet. record the channels by the
+          // sender's name, so it gets the dependents when added.
+          let mut unresolved = self.unresolved.lock().unwrap();
+          let dependents = unresolved.entry(sender_name.clone()).or_default();
+          for &(ch_id, dep_id) in deps.iter() {
+            let channels = dependents.entry(dep_id).or_default();
+            channels.push(ch_id);
+          }
+        }
+        sender_id
+      };
+      match sender_id {
+        // removed meanwhile, look it up again
+        Some(sender_id) if self.register_dependents(sender_id, deps.clone()).is_err() => {},
+        _ => { return; }
+      }
+    }
+  }
+
+  // the senders of the inputs must not trigger the task anymore
+  fn unregister_inputs(&self,
+                       task_id: TaskId,
+                       inputs: Vec<(ChannelId, SenderName)>)
+  {
+    for (_, sender_name) in inputs {
+      if let Some(sender_id) = self.resolve_task_id(&sender_name.0) {
+        let (l1, l2) = page::position(sender_id.0);
+        unsafe {
+          let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
+          if !l1_ptr.is_null() {
+            (*l1_ptr).unregister_dependent(l2, task_id);
+          }
+        }
+      }
+    }
+    // forget what the task was waiting for
+    let mut unresolved = self.unresolved.lock().unwrap();
+    for (_, dependents) in unresolved.iter_mut() {
+      dependents.remove(&task_id);
+    }
+    unresolved.retain(|_, dependents| !dependents.is_empty());
+  }
+
+  pub fn add_task(&self,
                   task: Box<Task+Send>,
                   rule: SchedulingRule)
       -> Result<TaskId, Error>
   {
+    self.add_task_with_affinity(task, rule, TaskAffinity::Any, Priority::Normal)
+  }
+
+  pub fn add_task_with_priority(&self,
+                                task: Box<dyn Task+Send>,
+                                rule: SchedulingRule,
+                                priority: Priority)
+      -> Result<TaskId, Error>
+  {
+    self.add_task_with_affinity(task, rule, TaskAffinity::Any, priority)
+  }
+
+  pub fn add_task_with_affinity(&self,
+                                task: Box<dyn Task+Send>,
+                                rule: SchedulingRule,
+                                affinity: TaskAffinity,
+                                priority: Priority)
+      -> Result<TaskId, Error>
+  {
+    let adding = self.adding.clone();
+    let adding_guard = adding.lock().unwrap();
     let result : Result<TaskId, Error>;
+    let affinity_key = match rule {
+      SchedulingRule::Blocking => page::blocking_affinity(),
+      _                        => self.affinity_key(&affinity),
+    };
+    let first_exec_at = self.first_exec_at(rule);
 
     {
       // limit the scope of the global task name hash's lock
@@ -132,49 +390,72 @@ impl SchedulerData {
       if result.is_err() { return result; }
     }
 
+    let mut watched = false;
     if let Ok(task_id) = result {
-      match rule {
-        SchedulingRule::OnMessage => {
-          let input_count = task.input_count();
-          // resolve input task ids
-          for i in 0..input_count {
-            if let Some(ref ch_id_sender_name) = task.input_id(ReceiverChannelId(i)) {
-              let ref sender_ch_id  = ch_id_sender_name.0;
-              let ref sender_name   = ch_id_sender_name.1;
-              // lookup sender id based on the name
-              match self.resolve_task_id(&sender_name.0) {
-                Some(sender_id) => {
-                  // the other task that the current one depends
-                  //  on is already registered.
-                  self.register_dependents(sender_id, vec![(*sender_ch_id, task_id)]);
-                }
-                None => {
-                  // the other task that the current one depends
-                  //  on is not added yet. record it as unresolved:
-                  let mut unresolved = self.unresolved.lock().unwrap();
-                  // register that this task needs the task id of the sender
-                  // - based on the sender name and chanel id
-                  let dependents = unresolved.entry(sender_name.0.clone()).or_insert(HashMap::new());
-                  let channels = dependents.entry(task_id).or_insert(Vec::new());
-                  channels.push(*sender_ch_id);
-                }
-              }
+      match self.watch(task_id, rule) {
+        Ok(w)  => { watched = w; },
+        Err(e) => {
+          let mut ids = self.ids.lock().unwrap();
+          ids.remove(task.name());
+          // the id may have been looked up meanwhile
+          let (l1, l2) = page::position(task_id.0);
+          unsafe {
+            let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
+            if !l1_ptr.is_null() {
+              (*l1_ptr).discard(l2);
             }
           }
-          self.mark_conditional_task(task_id);
+          return Result::Err(e);
+        }
+      }
+    }
+
+    if let Ok(task_id) = result {
+      // the senders are registered with after the lock is released
+      let mut inputs = Vec::new();
+      match rule {
+        // triggered by the poller thread
+        _ if watched => {
+          self.mark_conditional_task(task_id, PeriodLengthInUsec(page::never()));
+        },
+        SchedulingRule::OnMessage => {
+          inputs = input_ids(&*task);
+          self.mark_conditional_task(task_id, PeriodLengthInUsec(0));
+        },
+        SchedulingRule::OnMessageOrTimeout(timeout) => {
+          inputs = input_ids(&*task);
+          self.mark_conditional_task(task_id, timeout);
         },
         SchedulingRule::OnExternalEvent => {
-          self.mark_conditional_task(task_id);
+          self.mark_conditional_task(task_id, PeriodLengthInUsec(0));
         },
-        SchedulingRule::Periodic(period) => {
+        SchedulingRule::OnExternalEventOrTimeout(timeout) => {
+          self.mark_conditional_task(task_id, timeout);
+        },
+        SchedulingRule::Periodic(period) |
+        SchedulingRule::PeriodicAligned(period) => {
           self.mark_periodic_task(task_id, period);
         },
+        SchedulingRule::After(_) | SchedulingRule::At(_) => {
+          self.mark_periodic_task(task_id, PeriodLengthInUsec(0));
+        },
+        SchedulingRule::Blocking => {
+          // the sources run continuously, like Loop tasks
+          if task.input_count() > 0 {
+            inputs = input_ids(&*task);
+            self.mark_conditional_task(task_id, PeriodLengthInUsec(0));
+          }
+          self.blocking.lock().unwrap().0 += 1;
+        },
         // other scheduling rule types are currently ignored
         _ => {}
       }
 
       let output_count = task.output_count();
       let task_name    = task.name().clone();
+      let task_inputs  = input_ids(&*task);
+      let wrap = Box::new(wrap::new(task, rule, task_inputs));
+      self.counters.lock().unwrap().insert(task_id, wrap.counters().clone());
       {
         // make sure the next bucket exists when needed
         let (l1, l2) = page::position(task_id.0);
@@ -183,11 +464,14 @@ impl SchedulerData {
         }
 
         unsafe {
-          let l1_ptr = self.l1.get_unchecked_mut(l1).load(Ordering::Acquire);
+          let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
           if l1_ptr.is_null() == false {
-            // TODO : store scheduling rule somewhere ????
-            //(*l1_ptr).init_info(l2, output_count, rule);
-            (*l1_ptr).store(l2, task);
+            (*l1_ptr).set_affinity(l2, affinity_key);
+            // the task is stored on its level, so it is never executed on
+            // another one
+            let level = page::priority_level(priority);
+            self.priority_counts[level].fetch_add(1, Ordering::AcqRel);
+            (*l1_ptr).store(l2, wrap, first_exec_at, level);
           }
         }
       }
@@ -206,88 +490,927 @@ impl SchedulerData {
           }
           unresolved.remove(&task_name);
         }
-        self.register_dependents(task_id, register_these);
+        // the senders and the task itself may be running already, and
+        // they may be adding tasks themselves, so they are waited for
+        // without the lock
+        drop(adding_guard);
+        self.register_inputs(task_id, inputs);
+        self.connect_to_sender(&task_name, register_these);
       }
+      self.idle.wake();
     }
 
     result
   }
 
-  pub fn ticker(&mut self) {
+  pub fn remove_task(&self,
+                     id: &TaskId)
+      -> Result<Box<dyn Task+Send>, Error>
+  {
+    let (l1, l2) = page::position(id.0);
+    let l1_ptr = unsafe { self.l1.get_unchecked(l1).load(Ordering::Acquire) };
+    if l1_ptr.is_null() {
+      return Result::Err(Error::NonExistent);
+    }
+
+    // the name goes first, so the task is not looked up anymore while
+    // it is taken out of its slot. the tasks being added don't see it
+    // half removed.
+    let task_name = {
+      let adding = self.adding.clone();
+      let _adding = adding.lock().unwrap();
+      let mut ids = self.ids.lock().unwrap();
+      let found = ids.iter().find(|&(_, task_id)| *task_id == *id).map(|(name, _)| name.clone());
+      match found {
+        Some(name) => { ids.remove(&name); name },
+        None       => { return Result::Err(Error::NonExistent); }
+      }
+    };
+    self.counters.lock().unwrap().remove(id);
+
+    // the calls that looked up the id before give up on the slot once
+    // it is marked removed
+    let wrap = unsafe {
+      match (*l1_ptr).remove(l2) {
+        Ok((wrap, level)) => {
+          self.priority_counts[level].fetch_sub(1, Ordering::AcqRel);
+          wrap
+        },
+        Err(e) => { return Result::Err(e); }
+      }
+    };
+    if let SchedulingRule::Blocking = wrap.rule() {
+      self.blocking.lock().unwrap().0 -= 1;
+    }
+    let dependents = wrap.dependents().clone();
+    let task = wrap.into_task();
+    self.unregister_inputs(*id, input_ids(&*task));
+
+    // the receivers of the removed task wait for a new task with the
+    // same name, so a replacement gets connected again. it may have been
+    // added while this one was taken out.
+    self.connect_to_sender(&task_name, dependents);
+
+    {
+      let mut failures = self.failures.lock().unwrap();
+      failures.retain(|f| f.0 != *id);
+    }
+    self.supervisor.remove(*id);
+    self.watchdog.remove_task(*id);
+    self.poller.unregister(*id);
+
+    Ok(task)
+  }
+
+  pub fn set_priority(&self,
+                      id: &TaskId,
+                      priority: Priority)
+      -> Result<(), Error>
+  {
+    if self.resolve_task_name(*id).is_none() {
+      return Result::Err(Error::NonExistent);
+    }
+    let level = page::priority_level(priority);
+    let (l1, l2) = page::position(id.0);
+    unsafe {
+      let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
+      if l1_ptr.is_null() {
+        return Result::Err(Error::NonExistent);
+      }
+      // count the task on the new level first, so executors don't skip it
+      self.priority_counts[level].fetch_add(1, Ordering::AcqRel);
+      match (*l1_ptr).set_priority_level(l2, level) {
+        Ok(old_level) => {
+          self.priority_counts[old_level].fetch_sub(1, Ordering::AcqRel);
+          Ok(())
+        },
+        Err(e) => {
+          // removed meanwhile
+          self.priority_counts[level].fetch_sub(1, Ordering::AcqRel);
+          Result::Err(e)
+        }
+      }
+    }
+  }
+
+  pub fn set_rule(&self,
+                  id: &TaskId,
+                  rule: SchedulingRule)
+      -> Result<(), Error>
+  {
+    if self.resolve_task_name(*id).is_none() {
+      return Result::Err(Error::NonExistent);
+    }
+    let watched = match self.watch(*id, rule) {
+      Ok(w)  => w,
+      Err(e) => { return Result::Err(e); }
+    };
+    if !watched {
+      self.poller.unregister(*id);
+    }
+    let first_exec_at = self.first_exec_at(rule);
+    let (l1, l2) = page::position(id.0);
+    let found = unsafe {
+      let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
+      if l1_ptr.is_null() {
+        return Result::Err(Error::NonExistent);
+      }
+      (*l1_ptr).with_task(l2, |wrap| input_ids(wrap.task())).and_then(|inputs| {
+        (*l1_ptr).set_rule(l2, rule, first_exec_at, watched).map(|old_rule| (old_rule, inputs))
+      })
+    };
+    let (old_rule, inputs) = match found {
+      Ok(found) => found,
+      Err(e)    => {
+        // removed meanwhile
+        self.poller.unregister(*id);
+        return Result::Err(e);
+      }
+    };
+    {
+      let mut blocking = self.blocking.lock().unwrap();
+      if let SchedulingRule::Blocking = old_rule { blocking.0 -= 1; }
+      if let SchedulingRule::Blocking = rule { blocking.0 += 1; }
+    }
+    match (is_message_driven(old_rule), is_message_driven(rule)) {
+      (true, false) => {
+        self.unregister_inputs(*id, inputs);
+      },
+      (false, true) => {
+        self.register_inputs(*id, inputs);
+      },
+      _ => {}
+    }
+    self.idle.wake();
+    Ok(())
+  }
+
+  pub fn set_paused(&self,
+                    id: &TaskId,
+                    paused: bool)
+      -> Result<(), Error>
+  {
+    if self.resolve_task_name(*id).is_none() {
+      return Result::Err(Error::NonExistent);
+    }
+    let (l1, l2) = page::position(id.0);
+    unsafe {
+      let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
+      if l1_ptr.is_null() {
+        return Result::Err(Error::NonExistent);
+      }
+      if let Err(e) = (*l1_ptr).set_paused(l2, paused) {
+        return Result::Err(e);
+      }
+    }
+    if !paused {
+      self.idle.wake();
+    }
+    Ok(())
+  }
+
+  // when paused the executors keep running, but they don't execute tasks
+  pub fn set_all_paused(&self, paused: bool) {
+    self.paused.store(paused, Ordering::Release);
+    if paused {
+      // wait until the passes that were running finished
+      let counts = self.executor_loop_counts();
+      while !self.executors_settled(&counts) {
+        self.idle.wake();
+        unsafe { libc::usleep(100); }
+      }
+    } else {
+      self.idle.wake();
+    }
+  }
+
+  pub fn set_starvation_limit(&self, passes: usize) {
+    self.starvation_limit.store(passes, Ordering::Release);
+  }
+
+  pub fn supervise_tasks(&self,
+                         strategy: SupervisorStrategy,
+                         policy: RestartPolicy,
+                         members: Vec<TaskId>)
+      -> Result<(), Error>
+  {
+    for id in members.iter() {
+      if self.resolve_task_name(*id).is_none() {
+        return Result::Err(Error::NonExistent);
+      }
+    }
+    self.supervisor.add(strategy, policy, members);
+    Ok(())
+  }
+
+  fn supervise(&self, id: TaskId, failed: bool) {
+    let now = self.time_us.load(Ordering::Acquire);
+    if let Some((to_restart, at)) = self.supervisor.on_exit(id, failed, now) {
+      {
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|f| !to_restart.contains(&f.0));
+      }
+      for restart_id in to_restart {
+        let (l1, l2) = page::position(restart_id.0);
+        unsafe {
+          let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
+          if !l1_ptr.is_null() {
+            (*l1_ptr).schedule_restart(l2, at);
+          }
+        }
+      }
+      self.idle.wake();
+    }
+  }
+
+  fn task_counters(&self) -> Vec<(TaskId, Arc<stats::TaskCounters>)> {
+    let mut tasks : Vec<(TaskId, Arc<stats::TaskCounters>)> = {
+      let counters = self.counters.lock().unwrap();
+      counters.iter().map(|(id, c)| (*id, c.clone())).collect()
+    };
+    tasks.sort_by_key(|t| (t.0).0);
+    tasks
+  }
+
+  pub fn stats(&self) -> SchedulerStats {
+    // a running task is tried a few times, but not waited for. when it
+    // keeps running, it reports the channel positions from when it was
+    // last seen idle.
+    let mut task_stats = Vec::new();
+    for (id, counters) in self.task_counters() {
+      let (l1, l2) = page::position(id.0);
+      let mut found = None;
+      unsafe {
+        let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
+        if !l1_ptr.is_null() {
+          for _i in 0..100 {
+            found = (*l1_ptr).try_with_task(l2, |wrap| {
+              wrap.update_positions();
+              counters.snapshot(id)
+            });
+            if found.is_some() { break; }
+            thread::yield_now();
+          }
+        }
+      }
+      task_stats.push(found.unwrap_or_else(|| counters.snapshot(id)));
+    }
+
+    let executors = self.executors.lock().unwrap();
+    SchedulerStats{
+      tasks:      task_stats,
+      executors:  executors.iter().map(|e| e.snapshot()).collect(),
+    }
+  }
+
+  pub fn task_id(&self, name: &str) -> Option<TaskId> {
+    self.resolve_task_id(&String::from(name))
+  }
+
+  pub fn task_name(&self, id: &TaskId) -> Option<String> {
+    self.resolve_task_name(*id)
+  }
+
+  // like stats(), this doesn't wait for the running tasks
+  pub fn tasks(&self) -> Vec<TaskInfo> {
+    let all_paused = self.paused.load(Ordering::Acquire);
+    let mut infos = Vec::new();
+    for (id, counters) in self.task_counters() {
+      let (l1, l2) = page::position(id.0);
+      let state = unsafe {
+        let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
+        if l1_ptr.is_null() {
+          continue;
+        }
+        (*l1_ptr).state(l2)
+      };
+      let stats = counters.snapshot(id);
+      let inputs = counters.inputs().iter().map(|&(ch_id, ref sender_name)| {
+        let sender_id = self.resolve_task_id(&sender_name.0);
+        (ch_id, sender_name.clone(), sender_id)
+      }).collect();
+      infos.push(TaskInfo{
+        name:     stats.name,
+        id,
+        rule:     counters.rule(),
+        state:    if all_paused && state == TaskState::Running { TaskState::Paused } else { state },
+        inputs,
+        outputs:  Vec::new(),
+      });
+    }
+    // the outputs are the inputs of the receivers, whatever their rule
+    let connections : Vec<(TaskId, ChannelId, TaskId)> = infos.iter().flat_map(|info| {
+      info.inputs.iter().filter_map(move |&(ch_id, _, sender_id)| {
+        sender_id.map(|sender_id| (sender_id, ch_id, info.id))
+      })
+    }).collect();
+    for info in infos.iter_mut() {
+      info.outputs = connections.iter()
+        .filter(|c| c.0 == info.id)
+        .map(|c| (c.1, c.2))
+        .collect();
+    }
+    infos
+  }
+
+  pub fn notify_by_name(&self, name: &str) -> Result<(), Error> {
+    match self.task_id(name) {
+      Some(id) => self.notify(&id),
+      None     => Result::Err(Error::NonExistent),
+    }
+  }
+
+  fn registered_tasks(&self) -> Vec<(TaskId, String)> {
+    let mut tasks : Vec<(TaskId, String)> = {
+      let ids = self.ids.lock().unwrap();
+      ids.iter().map(|(name, id)| (*id, name.clone())).collect()
+    };
+    tasks.sort_by_key(|t| (t.0).0);
+    tasks
+  }
+
+  // clears the latency histograms of all tasks
+  pub fn reset_latency(&self) {
+    for (_, counters) in self.task_counters() {
+      counters.reset_latency();
+    }
+  }
+
+  // stops the tasks without inputs and returns them
+  pub fn stop_sources(&self) -> Vec<(TaskId, String)> {
+    let mut sources = Vec::new();
+    for (id, name) in self.registered_tasks() {
+      let (l1, l2) = page::position(id.0);
+      unsafe {
+        let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
+        if !l1_ptr.is_null() && (*l1_ptr).stop_if(l2, |wrap| wrap.is_source()).unwrap_or(false) {
+          sources.push((id, name));
+        }
+      }
+    }
+    sources
+  }
+
+  fn pending_tasks(&self, tasks: &[(TaskId, String)]) -> Vec<(TaskId, String)> {
+    let now = self.time_us.load(Ordering::Acquire);
+    let mut pending = Vec::new();
+    for &(id, ref name) in tasks {
+      let (l1, l2) = page::position(id.0);
+      unsafe {
+        let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
+        if !l1_ptr.is_null() && (*l1_ptr).is_pending(l2, now) {
+          pending.push((id, name.clone()));
+        }
+      }
+    }
+    pending
+  }
+
+  // the number of messages the tasks still have. the executors must be
+  // stopped.
+  pub fn pending_messages(&self, tasks: TaskList) -> Vec<(TaskId, String, usize)> {
+    tasks.into_iter().map(|(id, name)| {
+      let (l1, l2) = page::position(id.0);
+      let count = unsafe {
+        let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
+        if l1_ptr.is_null() {
+          0
+        } else {
+          (*l1_ptr).with_task(l2, |wrap| wrap.task().pending_messages()).unwrap_or(0)
+        }
+      };
+      (id, name, count)
+    }).collect()
+  }
+
+  fn executor_loop_counts(&self) -> Vec<usize> {
+    let executors = self.executors.lock().unwrap();
+    executors.iter().map(|e| e.loop_count()).collect()
+  }
+
+  // true when every running executor completed two passes since the
+  // counts were taken, so the tasks triggered before have been seen
+  fn executors_settled(&self, counts: &[usize]) -> bool {
+    let executors = self.executors.lock().unwrap();
+    executors.iter().zip(counts.iter()).all(|(e, count)| {
+      e.has_exited() || e.loop_count() >= count+2
+    })
+  }
+
+  // waits until the tasks with inputs processed their messages. returns
+  // the drained and the dropped tasks and if the timeout expired.
+  pub fn drain(&self,
+               sources: &[(TaskId, String)],
+               timeout: Duration)
+      -> (TaskList, TaskList, bool)
+  {
+    let deadline = Instant::now() + timeout;
+    let tasks : Vec<(TaskId, String)> = self.registered_tasks().into_iter()
+      .filter(|t| !sources.contains(t))
+      .collect();
+    let mut pending;
+    let mut timed_out = false;
+    loop {
+      let counts = self.executor_loop_counts();
+      pending = self.pending_tasks(&tasks);
+      if pending.is_empty() {
+        // a message may still be in flight, so let the executors do
+        // their passes and check again
+        while !self.executors_settled(&counts) && Instant::now() < deadline {
+          // parked executors need to be woken for every pass
+          self.idle.wake();
+          unsafe { libc::usleep(100); }
+        }
+        pending = self.pending_tasks(&tasks);
+        if pending.is_empty() && self.executors_settled(&counts) {
+          break;
+        }
+      }
+      if Instant::now() >= deadline {
+        timed_out = true;
+        break;
+      }
+      unsafe { libc::usleep(100); }
+    }
+    let drained = tasks.into_iter().filter(|t| !pending.contains(t)).collect();
+    (drained, pending, timed_out)
+  }
+
+  // calls the shutdown hook of every task. the executors must be stopped.
+  pub fn shutdown_tasks(&self) {
+    for (id, _) in self.registered_tasks() {
+      let (l1, l2) = page::position(id.0);
+      unsafe {
+        let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
+        if !l1_ptr.is_null() {
+          let _ = (*l1_ptr).with_task(l2, |wrap| wrap.shutdown());
+        }
+      }
+    }
+  }
+
+  pub fn failed_tasks(&self) -> Vec<(TaskId, String)> {
+    let failures = self.failures.lock().unwrap();
+    failures.clone()
+  }
+
+  pub fn set_idle_strategy(&self, strategy: IdleStrategy) {
+    self.idle.set_strategy(strategy);
+  }
+
+  // registers the executors before they start, so they show up in the
+  // stats right away
+  pub fn add_executors(&self, ids: &[usize]) {
+    self.add_counters(ids);
+    self.idle.add_executors(ids.len());
+  }
+
+  // the threads of the blocking pool, they have their own idle strategy
+  pub fn add_blocking_executors(&self, ids: &[usize]) {
+    self.add_counters(ids);
+    self.idle.add_pool_threads(ids.len());
+  }
+
+  fn add_counters(&self, ids: &[usize]) {
+    let mut executors = self.executors.lock().unwrap();
+    for id in ids {
+      executors.push(Arc::new(stats::new(*id)));
+    }
+  }
+
+  fn update_time(&self) {
+    if self.virtual_time.load(Ordering::Acquire) {
+      return;
+    }
+    let diff = self.start.elapsed();
+    let diff_us = diff.as_secs() as usize * 1_000_000 + diff.subsec_nanos() as usize / 1000;
+    self.time_us.fetch_max(diff_us, Ordering::AcqRel);
+  }
+
+  pub fn set_virtual_time(&self) {
+    self.virtual_time.store(true, Ordering::Release);
+  }
+
+  pub fn advance_time(&self, by: PeriodLengthInUsec) {
+    if self.virtual_time.load(Ordering::Acquire) {
+      self.time_us.fetch_add(by.0, Ordering::AcqRel);
+    }
+  }
+
+  // converts the due time from step() to a relative time
+  pub fn time_until(&self, next_at: usize) -> Option<PeriodLengthInUsec> {
+    if next_at >= page::never() {
+      return None;
+    }
+    let now = self.time_us.load(Ordering::Acquire);
+    Some(PeriodLengthInUsec(next_at.saturating_sub(now)))
+  }
+
+  pub fn time_us(&self) -> usize {
+    self.time_us.load(Ordering::Acquire)
+  }
+
+  // runs the ready tasks once on the calling thread, regardless of their
+  // affinity. returns the number of executed tasks and the earliest time
+  // a not yet due task needs to run.
+  pub fn step(&self) -> (usize, usize) {
+    // nobody else may update the time
+    self.update_time();
+    let mut private_data = prv::Private::new();
+    let mut skipped_passes = self.step_skipped_passes.lock().unwrap();
+    self.run_pass(0, None, &mut private_data, &mut skipped_passes)
+  }
+
+  // true once after the first file descriptor was registered
+  pub fn poller_needs_thread(&self) -> bool {
+    self.poller.needs_thread()
+  }
+
+  // triggers the tasks whose file descriptors became ready
+  pub fn poll_fds(&self) {
+    self.poller.block_all_signals();
+    loop {
+      let ready = self.poller.wait();
+      if self.stop.load(Ordering::Acquire) {
+        break;
+      }
+      for id in ready {
+        self.schedule_exec(&id);
+      }
+    }
+  }
+
+  // no signals can be registered once the executors are started
+  pub fn seal_signals(&self) {
+    self.poller.seal_signals();
+  }
+
+  // called by every thread of the scheduler when it starts, so the
+  // signals of the OnSignal tasks are only read through the poller
+  pub fn block_signals(&self) {
+    self.poller.block_signals();
+  }
+
+  pub fn add_thread(&self, t: thread::JoinHandle<()>) {
+    self.threads.lock().unwrap().push(t);
+  }
+
+  pub fn take_threads(&self) -> Vec<thread::JoinHandle<()>> {
+    mem::take(&mut *self.threads.lock().unwrap())
+  }
+
+  pub fn executor_count(&self) -> usize {
+    self.executors.lock().unwrap().len()
+  }
+
+  pub fn ticker(&self) {
+    let mut next_check = 0;
     loop {
       unsafe { libc::usleep(10); }
-      let diff = self.start.elapsed();
-      let diff_us = diff.as_secs() as usize * 1000_000 + diff.subsec_nanos() as usize / 1000;
-      self.time_us.store(diff_us, Ordering::Release);
+      self.update_time();
       // check stop state
       if self.stop.load(Ordering::Acquire) {
         break;
       }
+      let now = self.time_us.load(Ordering::Acquire);
+      if now >= next_check && self.watchdog_enabled.load(Ordering::Acquire) {
+        self.check_stuck_tasks(now);
+        next_check = now + 1000;
+      }
+      // parks when all executors are parked
+      self.idle.ticker_idle(&self.stop);
     }
   }
 
-  pub fn entry(&mut self, id: usize) {
+  // runs the ready tasks once. returns the number of executed tasks and
+  // the earliest time a not yet due task needs to run.
+  fn run_pass(&self,
+              id: usize,
+              exec_affinity: Option<(usize, usize)>,
+              private_data: &mut prv::Private,
+              skipped_passes: &mut [usize]) -> (usize, usize)
+  {
+    let l2_max = page::max_idx();
+    let mut executed = 0;
+    let mut next_at = usize::MAX;
 
-    let start = Instant::now();
-    let mut iter = 0u64;
-    let mut private_data = prv::Private::new();
+    let max_id = self.max_id.load(Ordering::Acquire);
+    private_data.ensure_size(max_id);
 
-    let l2_max = page::max_idx();
-    loop {
+    let trace_generation = self.trace_generation.load(Ordering::Acquire);
+    if trace_generation != private_data.trace_generation() {
+      // the calling thread of step() is traced as thread 0
+      let tid = match exec_affinity {
+        Some(_) => id+1,
+        None    => 0,
+      };
+      let buffer = self.trace_buffer(tid);
+      private_data.set_trace(trace_generation, buffer);
+    }
+
+    let budget_generation = self.budget_generation.load(Ordering::Acquire);
+    if budget_generation != private_data.budget_generation() {
+      private_data.set_budget(budget_generation, *self.budget.lock().unwrap());
+    }
 
-      let max_id = self.max_id.load(Ordering::Acquire);
-      private_data.ensure_size(max_id);
+    let (l1, l2) = page::position(max_id);
 
-      let (l1, l2) = page::position(max_id);
+    // higher priority levels first. when a higher level executed
+    // something, the lower levels only get their turn after they were
+    // skipped starvation_limit times.
+    let starvation_limit = self.starvation_limit.load(Ordering::Acquire);
+    let paused = self.paused.load(Ordering::Acquire);
+    for (level, skipped) in skipped_passes.iter_mut().enumerate() {
+      if paused || self.priority_counts[level].load(Ordering::Acquire) == 0 {
+        continue;
+      }
+      if executed > 0 && *skipped < starvation_limit {
+        *skipped += 1;
+        continue;
+      }
+      *skipped = 0;
 
       {
-        let l1_slice = self.l1.as_mut_slice();
+        let l1_slice = self.l1.as_slice();
 
         // go through all fully filled l2 buckets
         let mut l2_max_idx = l2_max;
         for l1_idx in 0..l1 {
           let l1_ptr = l1_slice[l1_idx].load(Ordering::Acquire);
-          unsafe {
+          let (page_executed, page_next_at) = unsafe {
             (*l1_ptr).eval(
               l2_max_idx,         // the max ID on the task page
               id,                 // the ID of the executor thread
-              &mut private_data,  // thread private data
+              exec_affinity,      // the tasks pinned to this executor, or all
+              level,              // the priority level to be executed
+              private_data,       // thread private data
               &self.time_us       // current time
-            );
-          }
+            )
+          };
+          executed += page_executed;
+          if page_next_at < next_at { next_at = page_next_at; }
         }
 
         // take care of the last, partially filled bucket
         l2_max_idx = l2;
         for l1_idx in l1..(l1+1) {
           let l1_ptr = l1_slice[l1_idx].load(Ordering::Acquire);
-          unsafe {
+          let (page_executed, page_next_at) = unsafe {
             (*l1_ptr).eval(
               l2_max_idx,         // the max ID on the task page
               id,                 // the ID of the executor thread
-              &mut private_data,  // thread private data
+              exec_affinity,      // the tasks pinned to this executor, or all
+              level,              // the priority level to be executed
+              private_data,       // thread private data
               &self.time_us       // current time
-            );
-          }
+            )
+          };
+          executed += page_executed;
+          if page_next_at < next_at { next_at = page_next_at; }
         }
       }
+    }
 
-      {
-        let to_trigger = private_data.to_trigger();
-        for t in to_trigger {
-          self.schedule_exec(t);
+    {
+      let to_trigger = private_data.to_trigger();
+      for t in to_trigger {
+        self.schedule_exec(t);
+      }
+    }
+    {
+      let failed = private_data.take_failures();
+      if !failed.is_empty() {
+        {
+          let mut failures = self.failures.lock().unwrap();
+          failures.extend(failed.iter().cloned());
+        }
+        for (task_id, _) in failed {
+          self.supervise(task_id, true);
+        }
+      }
+      for task_id in private_data.take_stopped() {
+        self.supervise(task_id, false);
+      }
+      // not supervised again, so they are not restarted in a loop
+      let not_restarted = private_data.take_not_restarted();
+      if !not_restarted.is_empty() {
+        let mut failures = self.failures.lock().unwrap();
+        for task_id in not_restarted {
+          failures.push((task_id, String::from("failed and cannot be restarted without a factory")));
         }
       }
-      private_data.clear();
+    }
+    for task_id in private_data.take_rearm() {
+      self.poller.rearm(task_id);
+    }
+    private_data.clear();
+    (executed, next_at)
+  }
+
+  pub fn entry(&self, id: usize, group: Option<String>) {
+    // the affinity keys that select this executor
+    let exec_affinity = (
+      page::executor_affinity(id),
+      match group {
+        Some(ref name) => page::group_affinity(self.group_id(name)),
+        None           => page::executor_affinity(id),
+      }
+    );
+    self.run_executor(id, exec_affinity);
+  }
+
+  // the threads of the blocking pool only run the Blocking tasks
+  pub fn blocking_entry(&self, id: usize) {
+    self.run_executor(id, (page::blocking_affinity(), page::blocking_affinity()));
+  }
+
+  pub fn set_watchdog(&self,
+                      threshold: Option<PeriodLengthInUsec>,
+                      action: WatchdogAction)
+  {
+    self.watchdog.set(threshold, action);
+    self.watchdog_enabled.store(self.watchdog.is_enabled(), Ordering::Release);
+  }
+
+  pub fn set_task_watchdog(&self,
+                           id: &TaskId,
+                           threshold: Option<PeriodLengthInUsec>)
+      -> Result<(), Error>
+  {
+    if self.resolve_task_name(*id).is_none() {
+      return Result::Err(Error::NonExistent);
+    }
+    self.watchdog.set_task(*id, threshold);
+    self.watchdog_enabled.store(self.watchdog.is_enabled(), Ordering::Release);
+    Ok(())
+  }
+
+  pub fn stuck_tasks(&self) -> Vec<StuckTask> {
+    self.watchdog.take_events()
+  }
+
+  // called by the ticker thread about every millisecond
+  fn check_stuck_tasks(&self, now: usize) {
+    let executors : Vec<Arc<stats::ExecutorCounters>> = self.executors.lock().unwrap().clone();
+    let running = executors.iter().filter_map(|e| {
+      e.running().map(|(id, since)| (e.id(), id, since))
+    }).collect();
+    let action = self.watchdog.action();
+    for (executor, id, since) in self.watchdog.check(&running, now) {
+      let counters = executors.iter().find(|e| e.id() == executor).unwrap();
+      let event = StuckTask{
+        id,
+        name:         self.resolve_task_name(id).unwrap_or_default(),
+        executor,
+        thread_name:  counters.thread_name(),
+        running_us:   now.saturating_sub(since),
+      };
+      match action {
+        WatchdogAction::Report => {},
+        WatchdogAction::MarkFailed => { counters.mark_stuck(id, since); },
+        WatchdogAction::Abort => {
+          eprintln!("stuck task: {:?}, aborting", event);
+          ::std::process::abort();
+        },
+      }
+      self.watchdog.push_event(event);
+    }
+  }
+
+  // starts a new trace, each thread records at most events_per_thread
+  // executions. zero stops tracing.
+  pub fn set_tracing(&self, events_per_thread: usize) {
+    let mut traces = self.traces.lock().unwrap();
+    if events_per_thread > 0 {
+      traces.1.clear();
+    }
+    traces.0 = events_per_thread;
+    self.trace_generation.fetch_add(1, Ordering::AcqRel);
+  }
+
+  fn trace_buffer(&self, tid: usize) -> Option<Arc<trace::TraceBuffer>> {
+    let mut traces = self.traces.lock().unwrap();
+    if traces.0 == 0 {
+      return None;
+    }
+    if let Some(buffer) = traces.1.iter().find(|b| b.tid() == tid) {
+      return Some(buffer.clone());
+    }
+    let buffer = Arc::new(trace::new(tid, self.start, traces.0));
+    traces.1.push(buffer.clone());
+    Some(buffer)
+  }
+
+  pub fn trace_json(&self) -> String {
+    let names = self.registered_tasks().into_iter().collect();
+    let traces = self.traces.lock().unwrap();
+    trace::to_json(&traces.1, &names)
+  }
+
+  // the executors take the budget over before their next pass
+  pub fn set_budget(&self, budget: ExecBudget) {
+    *self.budget.lock().unwrap() = budget;
+    self.budget_generation.fetch_add(1, Ordering::AcqRel);
+  }
+
+  // the task takes the budget over before its next execution
+  pub fn set_task_budget(&self,
+                         id: &TaskId,
+                         budget: Option<ExecBudget>)
+      -> Result<(), Error>
+  {
+    match self.counters.lock().unwrap().get(id) {
+      Some(counters) => {
+        counters.set_budget(budget);
+        Ok(())
+      },
+      None => Result::Err(Error::NonExistent),
+    }
+  }
+
+  pub fn set_max_blocking_threads(&self, n_threads: usize) {
+    self.max_blocking_threads.store(n_threads, Ordering::Release);
+  }
+
+  // called once the executors are running
+  pub fn start_blocking_pool(&self) {
+    self.blocking.lock().unwrap().2 = true;
+  }
+
+  // the pool grows up to one thread per Blocking task, once the executors
+  // are running. it never shrinks, the idle threads park. returns the
+  // number of threads to start, these are taken as started.
+  pub fn blocking_threads_needed(&self) -> usize {
+    let mut blocking = self.blocking.lock().unwrap();
+    if !blocking.2 {
+      return 0;
+    }
+    let max = self.max_blocking_threads.load(Ordering::Acquire);
+    let wanted = cmp::min(blocking.0, max);
+    if wanted <= blocking.1 {
+      return 0;
+    }
+    let needed = wanted - blocking.1;
+    blocking.1 = wanted;
+    needed
+  }
+
+  fn run_executor(&self, id: usize, exec_affinity: (usize, usize)) {
+    let start = Instant::now();
+    let mut iter = 0u64;
+    let mut busy_iter = 0u64;
+    let mut idle_passes = 0usize;
+    let mut skipped_passes = vec![0usize; page::priority_levels()];
+    let mut private_data = prv::Private::new();
+    let pool = exec_affinity.0 == page::blocking_affinity();
+    let counters = {
+      let executors = self.executors.lock().unwrap();
+      executors.iter().find(|e| e.id() == id).unwrap().clone()
+    };
+    counters.set_thread_name(thread::current().name().map(String::from));
+    private_data.set_counters(counters.clone());
+
+    loop {
+
+      let generation = self.idle.generation();
+      let (executed, next_at) = self.run_pass(id, Some(exec_affinity), &mut private_data, &mut skipped_passes);
 
       iter += 1;
+      if executed > 0 {
+        busy_iter += 1;
+      }
+      counters.update(iter, busy_iter, private_data.busy_ns());
 
       // check stop state
       if self.stop.load(Ordering::Acquire) {
         break;
       }
+
+      if executed > 0 {
+        idle_passes = 0;
+        if pool {
+          // a blocking source is executed again right away, this gives
+          // the threads waiting for the task a chance to take it
+          thread::yield_now();
+        }
+      } else {
+        idle_passes += 1;
+        let wait_us = if next_at == usize::MAX {
+          None
+        } else {
+          let now = self.time_us.load(Ordering::Acquire);
+          Some(next_at.saturating_sub(now))
+        };
+        if self.idle.on_idle(idle_passes, generation, wait_us, pool) {
+          // the ticker may have been parked too
+          self.update_time();
+          idle_passes = 0;
+        }
+      }
     }
 
+    counters.exited();
+
     if self.print_stats_enabled() {
       let diff = start.elapsed();
       let diff_ns = diff.as_secs() * 1_000_000_000 + diff.subsec_nanos() as u64;
@@ -297,17 +1420,18 @@ impl SchedulerData {
     }
   }
 
-  pub fn schedule_exec(&mut self, id: &TaskId) {
+  pub fn schedule_exec(&self, id: &TaskId) {
     let (l1, l2) = page::position(id.0);
     unsafe {
-      let l1_ptr = self.l1.get_unchecked_mut(l1).load(Ordering::Acquire);
+      let l1_ptr = self.l1.get_unchecked(l1).load(Ordering::Acquire);
       if l1_ptr.is_null() == false {
-        (*l1_ptr).schedule_exec(l2);
+        (*l1_ptr).schedule_exec(l2, self.time_us.load(Ordering::Acquire));
       }
     }
+    self.idle.wake();
   }
 
-  pub fn notify(&mut self, id: &TaskId) -> Result<(), Error> {
+  pub fn notify(&self, id: &TaskId) -> Result<(), Error> {
     if self.stop.load(Ordering::Acquire) {
       return Result::Err(Error::Stopping);
     }
@@ -316,16 +1440,20 @@ impl SchedulerData {
       return Result::Err(Error::NonExistent);
     }
     let (l1, l2) = page::position(id.0);
-    let l1_slice = self.l1.as_mut_slice();
+    let l1_slice = self.l1.as_slice();
     let l1_ptr = l1_slice[l1].load(Ordering::Acquire);
     if l1_ptr.is_null() {
       return Result::Err(Error::NonExistent);
     }
-    unsafe { Ok((*l1_ptr).schedule_exec(l2)) }
+    unsafe { (*l1_ptr).schedule_exec(l2, self.time_us.load(Ordering::Acquire)); }
+    self.idle.wake();
+    Ok(())
   }
 
-  pub fn stop(&mut self) {
+  pub fn stop(&self) {
     self.stop.store(true, Ordering::Release);
+    self.idle.wake_all();
+    self.poller.stop();
   }
 
   #[cfg(any(test,feature = "printstats"))]
@@ -335,6 +1463,22 @@ impl SchedulerData {
   fn print_stats_enabled(&self) -> bool { false }
 }
 
+fn is_message_driven(rule: SchedulingRule) -> bool {
+  matches!(rule, SchedulingRule::OnMessage |
+                 SchedulingRule::OnMessageOrTimeout(_) |
+                 SchedulingRule::Blocking)
+}
+
+fn input_ids(task: &dyn Task) -> Vec<(ChannelId, SenderName)> {
+  let mut inputs = Vec::with_capacity(task.input_count());
+  for i in 0..task.input_count() {
+    if let Some(ch_id_sender_name) = task.input_id(ReceiverChannelId(i)) {
+      inputs.push(ch_id_sender_name);
+    }
+  }
+  inputs
+}
+
 pub fn new() -> SchedulerData {
   SchedulerData::new()
 }
@@ -1,14 +1,15 @@
-
 use std::sync::Arc;
-use std::cell::UnsafeCell;
-use super::data;
+use std::thread;
+use super::{data, context};
+use super::super::{Task, Error, TaskId, SchedulingRule, TaskAffinity, SchedulerStats,
+  TaskInfo, Priority};
 
 pub struct SchedulerDataHandle {
-  handle: Arc<UnsafeCell<data::SchedulerData>>,
+  handle: Arc<data::SchedulerData>,
 }
 
 impl SchedulerDataHandle {
-  pub fn clone(&mut self) -> SchedulerDataHandle {
+  pub fn clone(&self) -> SchedulerDataHandle {
     SchedulerDataHandle{
       handle: self.handle.clone(),
     }
@@ -16,18 +17,144 @@ impl SchedulerDataHandle {
 
   fn new() -> SchedulerDataHandle {
     SchedulerDataHandle{
-      handle: Arc::new(UnsafeCell::new(data::new())),
+      handle: Arc::new(data::new()),
     }
   }
 
-  pub fn get(&mut self) -> &mut data::SchedulerData {
-    unsafe { &mut (*self.handle.get()) }
+  pub fn get(&self) -> &data::SchedulerData {
+    &self.handle
+  }
+
+  // for the wakers, which are shared between threads
+  pub fn notify(&self, id: &TaskId) {
+    let _ = self.handle.notify(id);
+  }
+
+  // starts the thread that waits for the file descriptors of the
+  // OnReadable and OnWritable tasks, once the first one was added
+  pub fn start_poller(&self) {
+    if self.get().poller_needs_thread() {
+      let data_handle = self.clone();
+      let t = thread::spawn(move || { data_handle.get().poll_fds(); });
+      self.get().add_thread(t);
+    }
   }
-}
 
-// all data access is atomic
-unsafe impl Send for SchedulerDataHandle { }
+  // grows the pool of the Blocking tasks. its threads are executors too,
+  // so they show up in the statistics.
+  pub fn start_blocking_threads(&self) {
+    let needed = self.get().blocking_threads_needed();
+    if needed == 0 {
+      return;
+    }
+    let first_id = self.get().executor_count();
+    let ids : Vec<usize> = (first_id..first_id+needed).collect();
+    self.get().add_blocking_executors(&ids);
+    for id in ids {
+      let data_handle = self.clone();
+      let t = thread::spawn(move || {
+        context::set_scheduler(data_handle.clone());
+        data_handle.get().block_signals();
+        data_handle.get().blocking_entry(id);
+      });
+      self.get().add_thread(t);
+    }
+  }
+}
 
 pub fn new() -> SchedulerDataHandle {
   SchedulerDataHandle::new()
 }
+
+// the part of the scheduler that can be shared with other threads and
+// used by the tasks while they execute
+pub struct SchedulerHandle {
+  data: SchedulerDataHandle,
+}
+
+impl Clone for SchedulerHandle {
+  fn clone(&self) -> SchedulerHandle {
+    SchedulerHandle{
+      data: self.data.clone(),
+    }
+  }
+}
+
+impl SchedulerHandle {
+  pub fn add_task(&self,
+                  task: Box<dyn Task+Send>,
+                  rule: SchedulingRule)
+    -> Result<TaskId, Error>
+  {
+    self.add_task_with_affinity(task, rule, TaskAffinity::Any)
+  }
+
+  pub fn add_task_with_priority(&self,
+                                task: Box<dyn Task+Send>,
+                                rule: SchedulingRule,
+                                priority: Priority)
+    -> Result<TaskId, Error>
+  {
+    let result = self.data.get().add_task_with_priority(task, rule, priority);
+    self.data.start_poller();
+    self.data.start_blocking_threads();
+    result
+  }
+
+  pub fn add_task_with_affinity(&self,
+                                task: Box<dyn Task+Send>,
+                                rule: SchedulingRule,
+                                affinity: TaskAffinity)
+    -> Result<TaskId, Error>
+  {
+    let result = self.data.get().add_task_with_affinity(task, rule, affinity, Priority::Normal);
+    self.data.start_poller();
+    self.data.start_blocking_threads();
+    result
+  }
+
+  pub fn notify(&self,
+                id: &TaskId)
+      -> Result<(), Error>
+  {
+    self.data.get().notify(id)
+  }
+
+  pub fn notify_by_name(&self,
+                        name: &str)
+      -> Result<(), Error>
+  {
+    self.data.get().notify_by_name(name)
+  }
+
+  pub fn task_id(&self,
+                 name: &str)
+      -> Option<TaskId>
+  {
+    self.data.get().task_id(name)
+  }
+
+  pub fn task_name(&self,
+                   id: &TaskId)
+      -> Option<String>
+  {
+    self.data.get().task_name(id)
+  }
+
+  // doesn't wait for the running tasks, see Scheduler::stats()
+  pub fn stats(&self) -> SchedulerStats {
+    self.data.get().stats()
+  }
+
+  pub fn tasks(&self) -> Vec<TaskInfo> {
+    self.data.get().tasks()
+  }
+
+  pub fn failed_tasks(&self) -> Vec<(TaskId, String)> {
+    self.data.get().failed_tasks()
+  }
+}
+
+pub fn scheduler_handle(data: SchedulerDataHandle) -> SchedulerHandle {
+  SchedulerHandle{ data }
+}
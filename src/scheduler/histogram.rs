@@ -0,0 +1,90 @@
+use super::super::{LatencyPercentiles};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// each power of two range is split into this many buckets, so the
+// reported values are within 25% of the measured ones
+const SUB_BUCKETS : u64 = 4;
+const N_BUCKETS : usize = 252;
+
+// a log-bucketed histogram of nanosecond values. recorded by the
+// executor running the task, read and reset by the other threads.
+pub struct Histogram {
+  counts:  Vec<AtomicU64>,
+  max:     AtomicU64,
+}
+
+fn index(value: u64) -> usize {
+  if value < SUB_BUCKETS {
+    return value as usize;
+  }
+  let log = 63 - value.leading_zeros() as u64;
+  let sub = (value >> (log-2)) & (SUB_BUCKETS-1);
+  ((log-1)*SUB_BUCKETS + sub) as usize
+}
+
+// the largest value that falls into the bucket
+fn upper_bound(idx: usize) -> u64 {
+  let idx = idx as u64;
+  if idx < SUB_BUCKETS {
+    return idx;
+  }
+  let log = idx/SUB_BUCKETS + 1;
+  let sub = idx%SUB_BUCKETS;
+  let lower = (SUB_BUCKETS+sub) << (log-2);
+  lower + (1 << (log-2)) - 1
+}
+
+impl Histogram {
+  // one thread records at a time
+  pub fn record(&self, value: u64) {
+    let bucket = &self.counts[index(value)];
+    bucket.store(bucket.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+    if value > self.max.load(Ordering::Relaxed) {
+      self.max.store(value, Ordering::Relaxed);
+    }
+  }
+
+  pub fn reset(&self) {
+    for c in self.counts.iter() {
+      c.store(0, Ordering::Relaxed);
+    }
+    self.max.store(0, Ordering::Relaxed);
+  }
+
+  // the value below which the given fraction of the values are
+  fn percentile(counts: &[u64], count: u64, max: u64, fraction: f64) -> u64 {
+    if count == 0 {
+      return 0;
+    }
+    let rank = ((count as f64) * fraction).ceil() as u64;
+    let mut seen = 0;
+    for (idx, c) in counts.iter().enumerate() {
+      seen += *c;
+      if seen >= rank {
+        return if upper_bound(idx) < max { upper_bound(idx) } else { max };
+      }
+    }
+    max
+  }
+
+  pub fn percentiles(&self) -> LatencyPercentiles {
+    // a copy, so the values recorded meanwhile don't skew the ranks
+    let counts : Vec<u64> = self.counts.iter().map(|c| c.load(Ordering::Relaxed)).collect();
+    let count = counts.iter().sum();
+    let max = self.max.load(Ordering::Relaxed);
+    LatencyPercentiles{
+      count,
+      p50:    Histogram::percentile(&counts, count, max, 0.5),
+      p99:    Histogram::percentile(&counts, count, max, 0.99),
+      p999:   Histogram::percentile(&counts, count, max, 0.999),
+      max,
+    }
+  }
+}
+
+pub fn new() -> Histogram {
+  Histogram{
+    counts:  (0..N_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+    max:     AtomicU64::new(0),
+  }
+}
@@ -0,0 +1,186 @@
+use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+use std::sync::{Mutex, Condvar};
+use std::thread;
+use std::time::{Duration};
+use super::super::{IdleStrategy, IdleSpinCount};
+
+// the threads of the blocking pool park soon, whatever the strategy of
+// the executors is, because there can be many of them
+const POOL_SPINS : usize = 100;
+
+pub struct Idle {
+  // the strategy of the executors, see encode()
+  strategy:       AtomicUsize,
+  pool_strategy:  IdleStrategy,
+  // the threads of the blocking pool, they need the wakeups even when
+  // the executors spin
+  pool_threads:   AtomicUsize,
+  // bumped on every wakeup, so a parking executor can tell if something
+  // happened since it started its last pass
+  generation:     AtomicUsize,
+  parked:         AtomicUsize,
+  executors:      AtomicUsize,
+  // the bool is true while the ticker thread is parked
+  lock:           Mutex<bool>,
+  executor_cv:    Condvar,
+  ticker_cv:      Condvar,
+}
+
+impl Idle {
+  pub fn set_strategy(&self, strategy: IdleStrategy) {
+    self.strategy.store(encode(strategy), Ordering::Release);
+  }
+
+  #[inline(always)]
+  fn strategy(&self) -> IdleStrategy {
+    decode(self.strategy.load(Ordering::Acquire))
+  }
+
+  pub fn add_executors(&self, n_threads: usize) {
+    self.executors.fetch_add(n_threads, Ordering::SeqCst);
+  }
+
+  // the pool threads are counted as executors too
+  pub fn add_pool_threads(&self, n_threads: usize) {
+    self.pool_threads.fetch_add(n_threads, Ordering::SeqCst);
+    self.add_executors(n_threads);
+  }
+
+  #[inline(always)]
+  fn spins_only(&self) -> bool {
+    match self.strategy() {
+      IdleStrategy::Spin => self.pool_threads.load(Ordering::SeqCst) == 0,
+      _ => false,
+    }
+  }
+
+  #[inline(always)]
+  pub fn generation(&self) -> usize {
+    if self.spins_only() {
+      return 0;
+    }
+    self.generation.load(Ordering::SeqCst)
+  }
+
+  // called when a task becomes ready to run
+  #[inline(always)]
+  pub fn wake(&self) {
+    if self.spins_only() {
+      return;
+    }
+    self.generation.fetch_add(1, Ordering::SeqCst);
+    if self.parked.load(Ordering::SeqCst) > 0 {
+      let _guard = self.lock.lock().unwrap();
+      self.executor_cv.notify_all();
+    }
+  }
+
+  // called on stop, to release everybody
+  pub fn wake_all(&self) {
+    self.generation.fetch_add(1, Ordering::SeqCst);
+    let _guard = self.lock.lock().unwrap();
+    self.executor_cv.notify_all();
+    self.ticker_cv.notify_all();
+  }
+
+  // called by the executor after each pass that ran nothing. returns true
+  // if the thread was parked, so the caller needs to refresh the time.
+  //
+  // - idle_passes: number of consecutive passes without work
+  // - seen_generation: the generation read before the pass started
+  // - wait_us: time until the next timed task is due, if any
+  // - pool: true on the threads of the blocking pool
+  pub fn on_idle(&self,
+                 idle_passes: usize,
+                 seen_generation: usize,
+                 wait_us: Option<usize>,
+                 pool: bool) -> bool
+  {
+    let strategy = if pool { self.pool_strategy } else { self.strategy() };
+    match strategy {
+      IdleStrategy::Spin => false,
+      IdleStrategy::SpinThenYield(spins) => {
+        if idle_passes > spins.0 {
+          thread::yield_now();
+        }
+        false
+      },
+      IdleStrategy::SpinThenPark(spins) => {
+        if idle_passes <= spins.0 {
+          return false;
+        }
+        if let Some(0) = wait_us {
+          return false;
+        }
+        let mut guard = self.lock.lock().unwrap();
+        self.parked.fetch_add(1, Ordering::SeqCst);
+        if self.generation.load(Ordering::SeqCst) == seen_generation {
+          guard = match wait_us {
+            Some(us) => {
+              self.executor_cv.wait_timeout(guard, Duration::from_micros(us as u64)).unwrap().0
+            },
+            None => {
+              self.executor_cv.wait(guard).unwrap()
+            }
+          };
+        }
+        self.parked.fetch_sub(1, Ordering::SeqCst);
+        if *guard {
+          // the ticker sleeps because everybody was parked
+          self.ticker_cv.notify_one();
+        }
+        true
+      },
+    }
+  }
+
+  // called by the ticker after each time update. the ticker only parks
+  // when all executors are parked, because then nobody reads the time.
+  pub fn ticker_idle(&self, stop: &AtomicBool) {
+    if let IdleStrategy::SpinThenPark(_) = self.strategy() {
+      let mut guard = self.lock.lock().unwrap();
+      loop {
+        let executors = self.executors.load(Ordering::SeqCst);
+        if executors == 0 ||
+           self.parked.load(Ordering::SeqCst) < executors ||
+           stop.load(Ordering::Acquire) {
+          break;
+        }
+        *guard = true;
+        guard = self.ticker_cv.wait(guard).unwrap();
+        *guard = false;
+      }
+    }
+  }
+}
+
+// the kind of the strategy in the low two bits, the spin count above
+fn encode(strategy: IdleStrategy) -> usize {
+  match strategy {
+    IdleStrategy::Spin                 => 0,
+    IdleStrategy::SpinThenYield(spins) => spins.0<<2 | 1,
+    IdleStrategy::SpinThenPark(spins)  => spins.0<<2 | 2,
+  }
+}
+
+fn decode(strategy: usize) -> IdleStrategy {
+  match strategy&3 {
+    1 => IdleStrategy::SpinThenYield(IdleSpinCount(strategy>>2)),
+    2 => IdleStrategy::SpinThenPark(IdleSpinCount(strategy>>2)),
+    _ => IdleStrategy::Spin,
+  }
+}
+
+pub fn new() -> Idle {
+  Idle{
+    strategy:       AtomicUsize::new(encode(IdleStrategy::Spin)),
+    pool_strategy:  IdleStrategy::SpinThenPark(IdleSpinCount(POOL_SPINS)),
+    pool_threads:   AtomicUsize::new(0),
+    generation:     AtomicUsize::new(0),
+    parked:         AtomicUsize::new(0),
+    executors:      AtomicUsize::new(0),
+    lock:           Mutex::new(false),
+    executor_cv:    Condvar::new(),
+    ticker_cv:      Condvar::new(),
+  }
+}
@@ -3,9 +3,54 @@ mod page;
 mod data;
 mod handle;
 mod prv;
+mod idle;
+mod supervisor;
+mod stats;
+mod affinity;
+mod context;
+mod poller;
+mod waker;
+mod trace;
+mod histogram;
+mod watchdog;
 
-use super::{Task, Error, TaskId, SchedulingRule};
-use std::thread::{spawn, JoinHandle};
+use super::{Task, Error, TaskId, SchedulingRule, IdleStrategy,
+  RestartPolicy, SupervisorStrategy, SchedulerStats, ThreadConfig, TaskAffinity,
+  Priority, ShutdownReport, WakeReason, PeriodLengthInUsec, PollResult,
+  PeriodicTimer, WatchdogAction, StuckTask, ExecBudget, TaskInfo, ChannelWrapper, ExpectedChannelState, ActualChannelState,
+  ChannelState};
+use super::elem::stream::{self, ReceiverStream};
+use std::task::{Waker};
+use std::time::{Duration, Instant};
+use std::thread::{Builder, JoinHandle};
+use std::sync::mpsc;
+
+// spawns a thread that applies its config, reports the result and waits
+// for the go signal before it calls the function
+fn spawn_configured<F>(config: ThreadConfig,
+                       result_tx: mpsc::Sender<Result<(), Error>>,
+                       fun: F)
+    -> Result<(JoinHandle<()>, mpsc::Sender<bool>), Error>
+  where F: FnOnce() + Send + 'static
+{
+  let (go_tx, go_rx) = mpsc::channel();
+  let mut builder = Builder::new();
+  if let Some(ref name) = config.name {
+    builder = builder.name(name.clone());
+  }
+  let spawned = builder.spawn(move || {
+    let result = affinity::apply(&config);
+    let applied = result.is_ok();
+    let _ = result_tx.send(result);
+    if applied && go_rx.recv() == Ok(true) {
+      fun();
+    }
+  });
+  match spawned {
+    Ok(t)  => Ok((t, go_tx)),
+    Err(e) => Result::Err(Error::OsError(e.raw_os_error().unwrap_or(0))),
+  }
+}
 
 pub struct Scheduler {
   data:     handle::SchedulerDataHandle,
@@ -18,13 +63,261 @@ impl Scheduler {
                   rule: SchedulingRule)
     -> Result<TaskId, Error>
   {
-    (*self.data.get()).add_task(task, rule)
+    let result = (*self.data.get()).add_task(task, rule);
+    self.data.start_poller();
+    self.data.start_blocking_threads();
+    result
+  }
+
+  // the task is executed on the priority level right from the start
+  pub fn add_task_with_priority(&mut self,
+                                task: Box<dyn Task+Send>,
+                                rule: SchedulingRule,
+                                priority: Priority)
+    -> Result<TaskId, Error>
+  {
+    let result = (*self.data.get()).add_task_with_priority(task, rule, priority);
+    self.data.start_poller();
+    self.data.start_blocking_threads();
+    result
+  }
+
+  // the task is only executed by the selected executor threads. the
+  // executor index is the position of the thread in the config passed
+  // to start_with_thread_config(..)
+  pub fn add_task_with_affinity(&mut self,
+                                task: Box<dyn Task+Send>,
+                                rule: SchedulingRule,
+                                affinity: TaskAffinity)
+    -> Result<TaskId, Error>
+  {
+    let result = (*self.data.get()).add_task_with_affinity(task, rule, affinity, Priority::Normal);
+    self.data.start_poller();
+    self.data.start_blocking_threads();
+    result
+  }
+
+  // records the executions of the tasks until stop_tracing() is called.
+  // each thread keeps at most events_per_thread executions, the rest is
+  // dropped. restarting the tracing drops the previous trace.
+  pub fn start_tracing(&mut self,
+                       events_per_thread: usize)
+  {
+    (*self.data.get()).set_tracing(events_per_thread)
+  }
+
+  pub fn stop_tracing(&mut self) {
+    (*self.data.get()).set_tracing(0)
+  }
+
+  // the recorded executions in the Chrome Trace Event format, which can
+  // be opened by chrome://tracing or ui.perfetto.dev
+  pub fn trace_json(&mut self) -> String {
+    (*self.data.get()).trace_json()
+  }
+
+  // the blocking pool grows up to this many threads (16 by default). a
+  // lower maximum doesn't stop the threads that were already started.
+  pub fn set_max_blocking_threads(&mut self,
+                                  n_threads: usize)
+  {
+    (*self.data.get()).set_max_blocking_threads(n_threads);
+    self.data.start_blocking_threads();
+  }
+
+  // must be called before the scheduler is started
+  pub fn set_idle_strategy(&mut self,
+                           strategy: IdleStrategy)
+      -> Result<(), Error>
+  {
+    if !self.threads.is_empty() {
+      return Result::Err(Error::Busy);
+    }
+    (*self.data.get()).set_idle_strategy(strategy);
+    Ok(())
+  }
+
+  // takes the task out of the scheduler once no executor runs it. the
+  // name of the task can be reused afterwards.
+  pub fn remove_task(&mut self,
+                     id: &TaskId)
+      -> Result<Box<dyn Task+Send>, Error>
+  {
+    (*self.data.get()).remove_task(id)
+  }
+
+  // tasks are added with Normal priority, unless added by
+  // add_task_with_priority(..)
+  pub fn set_priority(&mut self,
+                      id: &TaskId,
+                      priority: Priority)
+      -> Result<(), Error>
+  {
+    (*self.data.get()).set_priority(id, priority)
+  }
+
+  // switches the scheduling rule of a task. the task is executed once
+  // right away, then according to the new rule.
+  pub fn set_rule(&mut self,
+                  id: &TaskId,
+                  rule: SchedulingRule)
+      -> Result<(), Error>
+  {
+    let result = (*self.data.get()).set_rule(id, rule);
+    self.data.start_poller();
+    self.data.start_blocking_threads();
+    result
+  }
+
+  // the task keeps its channels, but it is not executed until resumed.
+  // waits until the task is not running.
+  pub fn pause(&mut self,
+               id: &TaskId)
+      -> Result<(), Error>
+  {
+    (*self.data.get()).set_paused(id, true)
+  }
+
+  pub fn resume(&mut self,
+                id: &TaskId)
+      -> Result<(), Error>
+  {
+    (*self.data.get()).set_paused(id, false)
+  }
+
+  // no task is executed until resume_all(). waits until the running
+  // tasks finished.
+  pub fn pause_all(&mut self) {
+    (*self.data.get()).set_all_paused(true);
+  }
+
+  pub fn resume_all(&mut self) {
+    (*self.data.get()).set_all_paused(false);
+  }
+
+  // when higher priority tasks were executed in a pass, the lower
+  // priority levels are skipped at most this many passes in a row.
+  // the default is 100.
+  pub fn set_starvation_limit(&mut self,
+                              passes: usize)
+  {
+    (*self.data.get()).set_starvation_limit(passes)
+  }
+
+  // restarts the task according to the policy when it stops or fails
+  pub fn set_restart_policy(&mut self,
+                            id: &TaskId,
+                            policy: RestartPolicy)
+      -> Result<(), Error>
+  {
+    (*self.data.get()).supervise_tasks(SupervisorStrategy::OneForOne, policy, vec![*id])
+  }
+
+  // puts the tasks into a supervisor group. with OneForAll, all members
+  // are restarted when one of them stops or fails. the restart policy
+  // and its restart intensity apply to the group as a whole.
+  pub fn add_supervisor_group(&mut self,
+                              strategy: SupervisorStrategy,
+                              policy: RestartPolicy,
+                              members: Vec<TaskId>)
+      -> Result<(), Error>
+  {
+    (*self.data.get()).supervise_tasks(strategy, policy, members)
+  }
+
+  // a snapshot of the task and executor thread statistics. the running
+  // tasks are not waited for, their channel positions are from when
+  // they were last seen idle.
+  pub fn stats(&mut self) -> SchedulerStats {
+    (*self.data.get()).stats()
+  }
+
+  // reports the executions that run longer than the threshold, see
+  // stuck_tasks(). None turns the watchdog off, except for the tasks with
+  // their own threshold.
+  pub fn set_watchdog(&mut self,
+                      threshold: Option<PeriodLengthInUsec>,
+                      action: WatchdogAction)
+  {
+    (*self.data.get()).set_watchdog(threshold, action)
+  }
+
+  // overrides the watchdog's threshold for the task, None exempts it
+  pub fn set_task_watchdog(&mut self,
+                           id: &TaskId,
+                           threshold: Option<PeriodLengthInUsec>)
+      -> Result<(), Error>
+  {
+    (*self.data.get()).set_task_watchdog(id, threshold)
+  }
+
+  // the budget of each execution, for the tasks without their own. the
+  // running tasks use it from their next execution.
+  pub fn set_budget(&mut self,
+                    budget: ExecBudget)
+  {
+    (*self.data.get()).set_budget(budget)
+  }
+
+  // None makes the task use the scheduler's budget again
+  pub fn set_task_budget(&mut self,
+                         id: &TaskId,
+                         budget: Option<ExecBudget>)
+      -> Result<(), Error>
+  {
+    (*self.data.get()).set_task_budget(id, budget)
+  }
+
+  // the stuck executions found by the watchdog since the last call
+  pub fn stuck_tasks(&mut self) -> Vec<StuckTask> {
+    (*self.data.get()).stuck_tasks()
+  }
+
+  // clears the latency histograms in the statistics of all tasks
+  pub fn reset_latency(&mut self) {
+    (*self.data.get()).reset_latency()
+  }
+
+  // the tasks that panicked during execution with the panic message.
+  // failed tasks are not executed again but they stay in the scheduler
+  // until they are removed.
+  pub fn failed_tasks(&mut self) -> Vec<(TaskId, String)> {
+    (*self.data.get()).failed_tasks()
   }
 
   pub fn start(&mut self) {
     self.start_with_threads(1);
   }
 
+  // the unconnected output channel of an added task as a stream for
+  // async code. the stream is woken after the task sent something.
+  pub fn receiver_stream<OutputValue: Send, OutputError: Send>(&mut self,
+                                                               channel: ChannelWrapper<OutputValue, OutputError>)
+      -> Result<ReceiverStream<OutputValue, OutputError>, Error>
+  {
+    let actual = match channel {
+      ChannelWrapper::SenderNotConnected(sender_ch_id, receiver, sender_name) => {
+        let waker = waker::output_waker();
+        return match (*self.data.get()).add_output_waker(&sender_name.0, sender_ch_id, waker.clone()) {
+          Ok(_)  => Ok(stream::receiver_stream(receiver, waker)),
+          Err(e) => Result::Err(e),
+        };
+      },
+      ChannelWrapper::ReceiverNotConnected(..) => ChannelState::ReceiverNotConnected,
+      ChannelWrapper::ConnectedReceiver(..)    => ChannelState::ConnectedReceiver,
+      ChannelWrapper::ConnectedSender(..)      => ChannelState::ConnectedSender,
+    };
+    Result::Err(Error::InvalidChannelState(
+      ExpectedChannelState(ChannelState::SenderNotConnected),
+      ActualChannelState(actual)))
+  }
+
+  // a handle for other threads. the tasks reach it with
+  // scheduler::handle() while they execute.
+  pub fn handle(&mut self) -> SchedulerHandle {
+    handle::scheduler_handle(self.data.clone())
+  }
+
   pub fn notify(&mut self,
                 id: &TaskId)
       -> Result<(), Error>
@@ -32,25 +325,106 @@ impl Scheduler {
     (*self.data.get()).notify(id)
   }
 
+  pub fn notify_by_name(&mut self,
+                        name: &str)
+      -> Result<(), Error>
+  {
+    (*self.data.get()).notify_by_name(name)
+  }
+
+  pub fn task_id(&mut self,
+                 name: &str)
+      -> Option<TaskId>
+  {
+    (*self.data.get()).task_id(name)
+  }
+
+  // the registered tasks in the order of their ids, with their state and
+  // connections
+  pub fn tasks(&mut self) -> Vec<TaskInfo> {
+    (*self.data.get()).tasks()
+  }
+
   pub fn start_with_threads(&mut self,
                             n_threads: usize)
   {
-    if n_threads == 0 {
-      return;
+    let executors = vec![ThreadConfig::new(); n_threads];
+    // the default config cannot fail
+    let _ = self.start_with_thread_config(executors, ThreadConfig::new());
+  }
+
+  // starts one executor thread for each of the executor configs, plus
+  // the ticker thread. if any of the threads cannot be configured, none
+  // of them is started and the error is returned.
+  pub fn start_with_thread_config(&mut self,
+                                  executors: Vec<ThreadConfig>,
+                                  ticker: ThreadConfig)
+      -> Result<(), Error>
+  {
+    if executors.is_empty() {
+      return Ok(());
     }
 
-    for _i in 0..n_threads {
-      let mut data_handle = self.data.clone();
-      let id = self.threads.len();
-      let t = spawn(move || { data_handle.get().entry(id); });
-      self.threads.push(t);
+    (*self.data.get()).seal_signals();
+
+    // the executor ids are the indices of the executor threads
+    let first_id = (*self.data.get()).executor_count();
+    let mut ids = Vec::with_capacity(executors.len());
+    let (result_tx, result_rx) = mpsc::channel();
+    let mut spawned = Vec::with_capacity(ids.capacity()+1);
+    let mut result = Ok(());
+
+    for config in executors {
+      let data_handle = self.data.clone();
+      let id = first_id + ids.len();
+      let group = config.group.clone();
+      match spawn_configured(config, result_tx.clone(), move || {
+        context::set_scheduler(data_handle.clone());
+        data_handle.get().block_signals();
+        data_handle.get().entry(id, group);
+      }) {
+        Ok(t)  => { spawned.push(t); ids.push(id); },
+        Err(e) => { result = Err(e); break; }
+      }
     }
 
-    {
-      let mut data_handle = self.data.clone();
-      let t = spawn(move || { data_handle.get().ticker(); });
-      self.threads.push(t);
+    if result.is_ok() {
+      let data_handle = self.data.clone();
+      match spawn_configured(ticker, result_tx.clone(), move || {
+        data_handle.get().block_signals();
+        data_handle.get().ticker();
+      }) {
+        Ok(t)  => spawned.push(t),
+        Err(e) => { result = Err(e); }
+      }
     }
+
+    for _i in 0..spawned.len() {
+      match result_rx.recv() {
+        Ok(Err(e)) => { if result.is_ok() { result = Err(e); } },
+        Ok(Ok(_))  => {},
+        Err(_)     => { result = Result::Err(Error::Stopping); },
+      }
+    }
+
+    if result.is_ok() {
+      (*self.data.get()).add_executors(&ids);
+    }
+
+    let go = result.is_ok();
+    for (t, go_tx) in spawned {
+      let _ = go_tx.send(go);
+      if go {
+        self.threads.push(t);
+      } else {
+        let _ = t.join();
+      }
+    }
+    if go {
+      (*self.data.get()).start_blocking_pool();
+      self.data.start_blocking_threads();
+    }
+    result
   }
 
   pub fn stop(&mut self) {
@@ -58,6 +432,105 @@ impl Scheduler {
     while let Some(t) = self.threads.pop() {
       t.join().unwrap();
     }
+    for t in (*self.data.get()).take_threads() {
+      t.join().unwrap();
+    }
+  }
+
+  // stops the sources, waits up to the timeout until the other tasks
+  // processed their pending messages, then stops the executors and calls
+  // the shutdown hook of every task
+  pub fn shutdown(&mut self,
+                  timeout: Duration) -> ShutdownReport
+  {
+    let sources = (*self.data.get()).stop_sources();
+    let (drained, dropped, timed_out) = (*self.data.get()).drain(&sources, timeout);
+    self.stop();
+    let dropped = (*self.data.get()).pending_messages(dropped);
+    (*self.data.get()).shutdown_tasks();
+    ShutdownReport{
+      drained,
+      dropped,
+      sources,
+      timed_out,
+    }
+  }
+
+  // selects what triggers the Periodic tasks that are added or switched
+  // to Periodic afterwards
+  pub fn set_periodic_timer(&mut self,
+                            timer: PeriodicTimer)
+  {
+    (*self.data.get()).set_periodic_timer(timer)
+  }
+
+  // a scheduler for testing: the time only moves with advance_time(..)
+  // and the tasks are executed by step() or run_until_idle() on the
+  // calling thread, in the order of their priorities and ids
+  pub fn new_with_virtual_time() -> Scheduler {
+    let sched = Scheduler::new();
+    (*sched.data.get()).set_virtual_time();
+    sched
+  }
+
+  // moves the virtual time forward. has no effect with the real clock.
+  pub fn advance_time(&mut self,
+                      by: PeriodLengthInUsec)
+  {
+    (*self.data.get()).advance_time(by)
+  }
+
+  // the scheduler's time since it was created
+  pub fn time(&mut self) -> PeriodLengthInUsec {
+    PeriodLengthInUsec((*self.data.get()).time_us())
+  }
+
+  // runs the ready tasks once on the calling thread and returns the
+  // number of executed tasks
+  pub fn step(&mut self) -> usize {
+    context::set_scheduler(self.data.clone());
+    (*self.data.get()).step().0
+  }
+
+  // runs the ready tasks once on the calling thread, so the scheduler can
+  // be driven by an external event loop instead of its own threads
+  pub fn run_once(&mut self) -> PollResult {
+    context::set_scheduler(self.data.clone());
+    let (executed, next_at) = (*self.data.get()).step();
+    PollResult{
+      executed,
+      next_timer:  (*self.data.get()).time_until(next_at),
+    }
+  }
+
+  // calls run_once() until no task is ready or the time is over. returns
+  // the total number of executed tasks and the time until the next timer.
+  pub fn poll(&mut self,
+              max_duration: Duration) -> PollResult
+  {
+    let start = Instant::now();
+    let mut result = PollResult{ executed: 0, next_timer: None };
+    loop {
+      let once = self.run_once();
+      result.executed += once.executed;
+      result.next_timer = once.next_timer;
+      if once.executed == 0 || start.elapsed() >= max_duration {
+        return result;
+      }
+    }
+  }
+
+  // calls step() until no task is ready. Loop tasks are always ready, so
+  // this doesn't return while they are running.
+  pub fn run_until_idle(&mut self) -> usize {
+    let mut executed = 0;
+    loop {
+      let n = self.step();
+      if n == 0 {
+        return executed;
+      }
+      executed += n;
+    }
   }
 
   pub fn new() -> Scheduler {
@@ -72,5 +545,42 @@ pub fn new() -> Scheduler {
   Scheduler::new()
 }
 
+// tells the element why it is executed. only valid while the scheduler
+// executes the task on the calling thread.
+pub fn wake_reason() -> WakeReason {
+  context::wake_reason()
+}
+
+// the number of ticks a PeriodicAligned task missed before the current
+// execution
+pub fn skipped_ticks() -> usize {
+  context::skipped_ticks()
+}
+
+// a waker that notifies the task that is being executed on the calling
+// thread, for the futures it polls. None outside of the scheduler.
+pub fn task_waker() -> Option<Waker> {
+  context::task_waker()
+}
+
+// the budget of the execution in progress on the current thread
+pub fn budget() -> ExecBudget {
+  context::budget()
+}
+
+// true when the execution in progress ran out of its max_ns budget
+pub fn budget_expired() -> bool {
+  context::budget_expired()
+}
+
+// the handle of the scheduler that executes tasks on the calling thread.
+// None on the other threads.
+pub fn handle() -> Option<SchedulerHandle> {
+  context::scheduler().map(handle::scheduler_handle)
+}
+
+pub use self::waker::{OutputWaker};
+pub use self::handle::{SchedulerHandle};
+
 #[cfg(test)]
 pub mod tests;
@@ -1,14 +1,29 @@
 
 use std::sync::atomic::{AtomicPtr, Ordering, AtomicUsize};
-use super::super::{Task, ChannelId, TaskId, PeriodLengthInUsec};
+use super::super::{ChannelId, TaskId, PeriodLengthInUsec, SchedulingRule,
+  Priority, WakeReason, SenderChannelId, TaskState, Error};
 use super::prv::{Private};
-use super::{wrap};
+use super::{wrap, context};
+use super::waker::{OutputWaker};
+use std::sync::{Arc};
 use std::ptr;
+use std::thread;
+use std::panic;
+use std::any::Any;
+use std::cmp;
 
-struct ExecFlags (AtomicUsize);
+// the flags and the time the task was triggered at plus one, zero when
+// not triggered since its last execution
+struct ExecFlags (AtomicUsize, AtomicUsize);
+
+// the third component is the period or the timeout in usec, the fourth is
+// the affinity key of the task, the fifth is its priority level. they are
+// changed while the executors read them.
+type SlotData = (AtomicPtr<wrap::TaskWrap>, ExecFlags, AtomicUsize, AtomicUsize, AtomicUsize);
 
 pub struct TaskPage {
-  data:    Vec<(AtomicPtr<wrap::TaskWrap>, ExecFlags, PeriodLengthInUsec)>,
+  id:      usize,
+  data:    Vec<SlotData>,
 }
 
 pub fn max_idx() -> usize {
@@ -21,14 +36,81 @@ pub fn position(idx: usize) -> (usize, usize) {
   (idx>>12, idx&0xfff)
 }
 
+pub fn task_id(l1: usize, l2: usize) -> TaskId {
+  // note: this is the inverse of position(idx)
+  TaskId(l1<<12 | l2)
+}
+
+// affinity keys: zero means any executor, odd keys select one executor
+// and even keys select a thread group
+pub fn executor_affinity(executor_id: usize) -> usize {
+  1 + 2*executor_id
+}
+
+pub fn group_affinity(group_id: usize) -> usize {
+  2 + 2*group_id
+}
+
+// selects the threads of the blocking pool, which don't run the tasks
+// of the other executors. beyond the group keys in practice.
+pub fn blocking_affinity() -> usize {
+  usize::MAX - 1
+}
+
+// the exec time of tasks that only run when notified
+pub fn never() -> usize {
+  usize::MAX >> 8
+}
+
+// the first point of the period's grid at or after the time
+pub fn grid_point(time_us: usize, period: PeriodLengthInUsec) -> usize {
+  let period = if period.0 == 0 { 1 } else { period.0 };
+  time_us.div_ceil(period) * period
+}
+
+// conditional tasks without a timeout are executed this often anyway
+pub fn default_timeout_us() -> usize {
+  10_000_000
+}
+
+pub fn priority_levels() -> usize {
+  3
+}
+
+// the level of the empty and the removed slots, which is never executed
+pub fn no_priority_level() -> usize {
+  priority_levels()
+}
+
+pub fn priority_level(priority: Priority) -> usize {
+  // note: this must be aligned with priority_levels()
+  match priority {
+    Priority::High   => 0,
+    Priority::Normal => 1,
+    Priority::Low    => 2,
+  }
+}
+
+fn panic_message(payload: Box<dyn Any+Send>) -> String {
+  if let Some(msg) = payload.downcast_ref::<&str>() {
+    String::from(*msg)
+  } else if let Some(msg) = payload.downcast_ref::<String>() {
+    msg.clone()
+  } else {
+    String::from("unknown panic")
+  }
+}
+
 impl TaskPage {
   pub fn store(&mut self,
                idx: usize,
-               task: Box<Task+Send>)
+               wrap: Box<wrap::TaskWrap>,
+               at: usize,
+               level: usize)
   {
-    let wrap = Box::new(wrap::new(task));
     let slice = self.data.as_mut_slice();
     let data_ref = &mut slice[idx];
+    data_ref.4.store(level, Ordering::Release);
     let old = data_ref.0.swap(Box::into_raw(wrap), Ordering::AcqRel);
     if old.is_null() == false {
       // make sure we drop old pointers when swapped, although
@@ -36,8 +118,31 @@ impl TaskPage {
       // of atomically increasing indices
       let _b = unsafe { Box::from_raw(old) };
     }
-    // clear the stopped flag too
-    (data_ref.1).0.fetch_and(63-16, Ordering::AcqRel);
+    // set the first exec time and clear the stopped flag too
+    let _ = (data_ref.1).0.fetch_update(Ordering::AcqRel, Ordering::Acquire, |f| {
+      Some(at<<8 | (f&(255-16)))
+    });
+  }
+
+  pub fn set_affinity(&mut self, idx: usize, affinity: usize) {
+    let slice = self.data.as_mut_slice();
+    slice[idx].3.store(affinity, Ordering::Release);
+  }
+
+  // returns the previous priority level. fails if the slot holds no
+  // task.
+  pub fn set_priority_level(&mut self, idx: usize, level: usize) -> Result<usize, Error> {
+    let current = &self.data[idx].4;
+    let mut old_level = current.load(Ordering::Acquire);
+    loop {
+      if old_level == no_priority_level() {
+        return Result::Err(Error::NonExistent);
+      }
+      match current.compare_exchange(old_level, level, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_)       => { return Ok(old_level); },
+        Err(actual) => { old_level = actual; }
+      }
+    }
   }
 
   pub fn set_dependents_flag(&mut self, idx: usize) {
@@ -50,97 +155,488 @@ impl TaskPage {
     let slice = self.data.as_mut_slice();
     let mut data_ref = &mut slice[idx];
     (data_ref.1).0.fetch_or(4, Ordering::Release);
-    data_ref.2 = period;
+    data_ref.2.store(period.0, Ordering::Release);
   }
 
-  pub fn set_conditional_exec_flag(&mut self, idx: usize) {
+  // a zero timeout selects the default
+  pub fn set_conditional_exec_flag(&mut self, idx: usize, timeout: PeriodLengthInUsec) {
     let slice = self.data.as_mut_slice();
     let data_ref = &mut slice[idx];
     (data_ref.1).0.fetch_or(32, Ordering::Release);
+    data_ref.2.store(timeout.0, Ordering::Release);
   }
 
-  pub fn schedule_exec(&mut self, idx: usize) {
+  pub fn schedule_exec(&mut self, idx: usize, now: usize) {
     let slice = self.data.as_mut_slice();
     let data_ref = &mut slice[idx];
+    // the first trigger since the last execution counts for the delay
+    let _ = (data_ref.1).1.compare_exchange(0, now+1, Ordering::AcqRel, Ordering::Relaxed);
     // clear exec time
-    (data_ref.1).0.fetch_and(63, Ordering::Acquire);
+    (data_ref.1).0.fetch_and(255, Ordering::Acquire);
   }
 
   // has_dependents: 1
-  // ???: 2
+  // restart-pending: 2
   // delayed: 4
-  // ???: 8
+  // failed: 8
   // stopped-flag: 16
   // conditiona: 32
+  // paused: 64
+  // removed: 128
 
   pub fn register_dependents(&mut self,
                              idx: usize,
                              deps: Vec<(ChannelId, TaskId)>)
+      -> Result<(), Error>
+  {
+    // the flags are left alone, because the executor may update them
+    // while it runs the task
+    self.with_task(idx, |wrap| wrap.register_dependents(deps))
+  }
+
+  pub fn add_output_waker(&mut self,
+                          idx: usize,
+                          ch_id: SenderChannelId,
+                          waker: Arc<OutputWaker>)
+      -> Result<(), Error>
+  {
+    self.with_task(idx, |wrap| wrap.add_output_waker(ch_id, waker))
+  }
+
+  pub fn unregister_dependent(&mut self,
+                              idx: usize,
+                              id: TaskId)
+  {
+    // the flags are left alone, because the executor may update them
+    // while it runs the task
+    let _ = self.with_task(idx, |wrap| wrap.unregister_dependent(id));
+  }
+
+  // true once the task was removed from the slot. such a slot stays
+  // empty, because the task ids are not reused.
+  fn is_removed(data_ref: &SlotData) -> bool {
+    (data_ref.1).0.load(Ordering::Acquire) & 128 == 128
+  }
+
+  // runs the function on the task once no executor runs it. fails if
+  // the task was removed.
+  pub fn with_task<F, R>(&mut self,
+                         idx: usize,
+                         fun: F) -> Result<R, Error>
+      where F: FnOnce(&mut wrap::TaskWrap) -> R
+  {
+    let slice = self.data.as_mut_slice();
+    let data_ref = &mut slice[idx];
+    loop {
+      let wrk = data_ref.0.swap(ptr::null_mut::<wrap::TaskWrap>(), Ordering::AcqRel);
+      if !wrk.is_null() {
+        let result = unsafe { fun(&mut (*wrk)) };
+        data_ref.0.store(wrk, Ordering::Release);
+        return Ok(result);
+      }
+      if TaskPage::is_removed(data_ref) {
+        return Result::Err(Error::NonExistent);
+      }
+      thread::yield_now();
+    }
+  }
+
+  // like with_task, but gives up when the task is being executed
+  pub fn try_with_task<F, R>(&mut self,
+                             idx: usize,
+                             fun: F) -> Option<R>
+      where F: FnOnce(&mut wrap::TaskWrap) -> R
+  {
+    let slice = self.data.as_mut_slice();
+    let data_ref = &mut slice[idx];
+    let wrk = data_ref.0.swap(ptr::null_mut::<wrap::TaskWrap>(), Ordering::AcqRel);
+    if wrk.is_null() {
+      return None;
+    }
+    let result = unsafe { fun(&mut (*wrk)) };
+    data_ref.0.store(wrk, Ordering::Release);
+    Some(result)
+  }
+
+  // sets the stopped flag when the predicate is true for the task.
+  // fails if the task was removed.
+  pub fn stop_if<F>(&mut self,
+                    idx: usize,
+                    pred: F) -> Result<bool, Error>
+      where F: FnOnce(&wrap::TaskWrap) -> bool
+  {
+    let slice = self.data.as_mut_slice();
+    let data_ref = &mut slice[idx];
+    loop {
+      let wrk = data_ref.0.swap(ptr::null_mut::<wrap::TaskWrap>(), Ordering::AcqRel);
+      if !wrk.is_null() {
+        // the executor stores the flags before it gives back the task,
+        // so the stopped flag cannot be overwritten
+        let stop = unsafe { pred(&(*wrk)) };
+        if stop {
+          (data_ref.1).0.fetch_or(16, Ordering::AcqRel);
+        }
+        data_ref.0.store(wrk, Ordering::Release);
+        return Ok(stop);
+      }
+      if TaskPage::is_removed(data_ref) {
+        return Result::Err(Error::NonExistent);
+      }
+      thread::yield_now();
+    }
+  }
+
+  // switches the rule of the task and schedules it for execution at the
+  // given time. returns the previous rule, fails if the task was
+  // removed.
+  pub fn set_rule(&mut self,
+                  idx: usize,
+                  rule: SchedulingRule,
+                  at: usize,
+                  watched: bool) -> Result<SchedulingRule, Error>
+  {
+    let slice = self.data.as_mut_slice();
+    let data_ref = &mut slice[idx];
+    loop {
+      let wrk = data_ref.0.swap(ptr::null_mut::<wrap::TaskWrap>(), Ordering::AcqRel);
+      if !wrk.is_null() {
+        let old_rule = unsafe { (*wrk).set_rule(rule) };
+        // clear the delayed and conditional flags and the exec time
+        (data_ref.1).0.fetch_and(255-4-32, Ordering::AcqRel);
+        match rule {
+          // triggered by the poller thread
+          _ if watched => {
+            data_ref.2.store(never(), Ordering::Release);
+            (data_ref.1).0.fetch_or(32, Ordering::AcqRel);
+          },
+          SchedulingRule::OnMessage | SchedulingRule::OnExternalEvent => {
+            data_ref.2.store(0, Ordering::Release);
+            (data_ref.1).0.fetch_or(32, Ordering::AcqRel);
+          },
+          SchedulingRule::OnMessageOrTimeout(timeout) |
+          SchedulingRule::OnExternalEventOrTimeout(timeout) => {
+            data_ref.2.store(timeout.0, Ordering::Release);
+            (data_ref.1).0.fetch_or(32, Ordering::AcqRel);
+          },
+          SchedulingRule::Periodic(period) |
+          SchedulingRule::PeriodicAligned(period) => {
+            data_ref.2.store(period.0, Ordering::Release);
+            (data_ref.1).0.fetch_or(4, Ordering::AcqRel);
+          },
+          SchedulingRule::After(_) | SchedulingRule::At(_) => {
+            data_ref.2.store(0, Ordering::Release);
+            (data_ref.1).0.fetch_or(4, Ordering::AcqRel);
+          },
+          // the blocking sources run continuously
+          SchedulingRule::Blocking if !unsafe { (*wrk).is_source() } => {
+            data_ref.2.store(0, Ordering::Release);
+            (data_ref.1).0.fetch_or(32, Ordering::AcqRel);
+          },
+          _ => {}
+        }
+        // the blocking pool only runs the Blocking tasks, the other
+        // executors don't. leaving the pool resets the affinity.
+        match (old_rule, rule) {
+          (_, SchedulingRule::Blocking) => { data_ref.3.store(blocking_affinity(), Ordering::Release); },
+          (SchedulingRule::Blocking, _) => { data_ref.3.store(0, Ordering::Release); },
+          _ => {}
+        }
+        (data_ref.1).0.fetch_or(at<<8, Ordering::AcqRel);
+        data_ref.0.store(wrk, Ordering::Release);
+        return Ok(old_rule);
+      }
+      if TaskPage::is_removed(data_ref) {
+        return Result::Err(Error::NonExistent);
+      }
+      thread::yield_now();
+    }
+  }
+
+  // sets or clears the paused flag. the task is not running when this
+  // returns. fails if the task was removed.
+  pub fn set_paused(&mut self,
+                    idx: usize,
+                    paused: bool)
+      -> Result<(), Error>
+  {
+    let slice = self.data.as_mut_slice();
+    let data_ref = &mut slice[idx];
+    loop {
+      let wrk = data_ref.0.swap(ptr::null_mut::<wrap::TaskWrap>(), Ordering::AcqRel);
+      if !wrk.is_null() {
+        if paused {
+          (data_ref.1).0.fetch_or(64, Ordering::AcqRel);
+        } else {
+          (data_ref.1).0.fetch_and(!64, Ordering::AcqRel);
+        }
+        data_ref.0.store(wrk, Ordering::Release);
+        return Ok(());
+      }
+      if TaskPage::is_removed(data_ref) {
+        return Result::Err(Error::NonExistent);
+      }
+      thread::yield_now();
+    }
+  }
+
+  pub fn state(&self, idx: usize) -> TaskState {
+    let flags = (self.data[idx].1).0.load(Ordering::Acquire);
+    if flags&8 == 8 {
+      TaskState::Failed
+    } else if flags&16 == 16 {
+      TaskState::Stopped
+    } else if flags&64 == 64 {
+      TaskState::Paused
+    } else {
+      TaskState::Running
+    }
+  }
+
+  // true for the tasks that have messages waiting, are running or are
+  // triggered
+  pub fn is_pending(&mut self,
+                    idx: usize,
+                    now: usize) -> bool
+  {
+    let flags = (self.data[idx].1).0.load(Ordering::Acquire);
+    if flags&16 == 16 {
+      return false;
+    }
+    if flags&32 == 32 && flags>>8 <= now {
+      return true;
+    }
+    self.try_with_task(idx, |wrap| wrap.task().pending_messages() > 0).unwrap_or(true)
+  }
+
+  // marks the task to be restarted at the given time. the task gets
+  // restarted by the executor that runs it next. a removed task is left
+  // alone.
+  pub fn schedule_restart(&mut self,
+                          idx: usize,
+                          at: usize)
   {
     let slice = self.data.as_mut_slice();
     let data_ref = &mut slice[idx];
     let atomic_flags = &mut data_ref.1;
-    let delay_exec : u64 = 0xffffffffffffff << 6;
-    let flags = atomic_flags.0.fetch_or(delay_exec as usize, Ordering::Acquire);
+    let delay_exec : u64 = 0xffffffffffffff << 8;
+    atomic_flags.0.fetch_or(delay_exec as usize, Ordering::Acquire);
     loop {
+      if atomic_flags.0.load(Ordering::Acquire) & 128 == 128 {
+        break;
+      }
       let wrk = data_ref.0.swap(ptr::null_mut::<wrap::TaskWrap>(), Ordering::AcqRel);
       if !wrk.is_null() {
-        unsafe { (*wrk).register_dependents(deps); }
+        // clear the stopped flag, set restart-pending. the failed flag
+        // stays until the executor recreated the task.
+        let flags = atomic_flags.0.load(Ordering::Acquire);
+        let new_flags : usize = at<<8 | (flags&(255-16)) | 2;
+        atomic_flags.0.store(new_flags, Ordering::Release);
         data_ref.0.store(wrk, Ordering::Release);
-        atomic_flags.0.store(flags, Ordering::Release);
         break;
       }
       atomic_flags.0.fetch_or(delay_exec as usize, Ordering::Acquire);
     }
   }
 
+  // takes the task out of its slot, waiting for the executor if it is
+  // currently running. the slot is marked removed, so the calls waiting
+  // for the task give up. returns the task with its priority level,
+  // fails if the task was removed already.
+  pub fn remove(&mut self, idx: usize) -> Result<(Box<wrap::TaskWrap>, usize), Error> {
+    let slice = self.data.as_mut_slice();
+    let data_ref = &mut slice[idx];
+    (data_ref.1).0.fetch_or(16, Ordering::AcqRel);
+    loop {
+      let wrk = data_ref.0.swap(ptr::null_mut::<wrap::TaskWrap>(), Ordering::AcqRel);
+      if !wrk.is_null() {
+        // the executor may have overwritten the stopped flag before it
+        // returned the task, so reset the slot to the default state
+        (data_ref.1).0.store(16|128, Ordering::Release);
+        (data_ref.1).1.store(0, Ordering::Release);
+        data_ref.2.store(0, Ordering::Release);
+        data_ref.3.store(0, Ordering::Release);
+        let level = data_ref.4.swap(no_priority_level(), Ordering::AcqRel);
+        return Ok((unsafe { Box::from_raw(wrk) }, level));
+      }
+      if TaskPage::is_removed(data_ref) {
+        return Result::Err(Error::NonExistent);
+      }
+      thread::yield_now();
+    }
+  }
+
+  // marks the slot of a task that could not be added removed
+  pub fn discard(&mut self, idx: usize) {
+    let slice = self.data.as_mut_slice();
+    (slice[idx].1).0.fetch_or(16|128, Ordering::AcqRel);
+  }
+
+  // returns the number of executed tasks and the earliest time a
+  // not yet due task needs to run
   #[inline(always)]
   pub fn eval(&mut self,
               l2_max_idx: usize,
               exec_thread_id: usize,
+              exec_affinity: Option<(usize, usize)>,
+              priority_level: usize,
               private_data: &mut Private,
-              time_us: &AtomicUsize)
+              time_us: &AtomicUsize) -> (usize, usize)
   {
-    let mut skip    = exec_thread_id;
-    let mut l2_idx  = 0;
-    let mut now     = time_us.load(Ordering::Acquire);
+    let mut skip      = exec_thread_id;
+    let mut l2_idx    = 0;
+    let mut now       = time_us.load(Ordering::Acquire);
+    let mut executed  = 0;
+    let mut next_at   = usize::MAX;
 
-    for act_data in &mut self.data {
+    let page_id = self.id;
+    for (l2_pos, act_data) in self.data.iter_mut().enumerate() {
       if l2_idx >= l2_max_idx { break; }
-      let flags = (act_data.1).0.load(Ordering::Acquire);
-      let stopped = flags&16;
-      // execute if not stopped and time is OK
-      if stopped == 0 {
-        let next_execution_at = flags >> 6;
-        if next_execution_at <= now {
-          let wrk = act_data.0.swap(ptr::null_mut::<wrap::TaskWrap>(), Ordering::AcqRel);
+      let mut flags = (act_data.1).0.load(Ordering::Acquire);
+      let stopped = flags&(16|64);
+      // skip the tasks pinned to other executors
+      let affinity = act_data.3.load(Ordering::Acquire);
+      let runs_here = match exec_affinity {
+        Some(keys) => {
+          affinity == keys.0 || affinity == keys.1 ||
+            (affinity == 0 && keys.0 != blocking_affinity())
+        },
+        None       => true,
+      };
+      // execute if not stopped or paused, has the current priority and time is OK
+      if stopped == 0 && runs_here && act_data.4.load(Ordering::Relaxed) == priority_level {
+        let next_execution_at = flags >> 8;
+        if next_execution_at > now {
+          if next_execution_at < next_at {
+            next_at = next_execution_at;
+          }
+        } else {
+          let mut wrk = act_data.0.swap(ptr::null_mut::<wrap::TaskWrap>(), Ordering::AcqRel);
+          if !wrk.is_null() {
+            // pause() and stop_if() set their flags while they hold the
+            // task, so the flags loaded before taking it may be stale
+            flags = (act_data.1).0.load(Ordering::Acquire);
+            if flags&(16|64) != 0 {
+              act_data.0.store(wrk, Ordering::Release);
+              wrk = ptr::null_mut();
+            }
+          }
           if !wrk.is_null() {
+            executed += 1;
 
-            let mut stop = false;
-            unsafe {
-              // flags&1 is the dependents flag
-              (*wrk).execute(flags&1 == 1, &mut stop, private_data);
+            let restart = flags&2 == 2;
+            let failed = flags&8 == 8;
+            if restart {
+              (act_data.1).0.fetch_and(!(2|8), Ordering::AcqRel);
+              flags &= !(2|8);
+            }
+
+            // push the exec time of conditional and delayed tasks far
+            // ahead, so the triggers that arrive during the execution are
+            // not lost
+            if flags&(4|32) != 0 {
+              (act_data.1).0.fetch_or(usize::MAX << 8, Ordering::AcqRel);
+            }
+            // the time of the first trigger since the last execution. the
+            // first execution after the task was added or its rule was
+            // changed is due right away without a trigger.
+            let triggered_at = (act_data.1).1.swap(0, Ordering::AcqRel);
+            let reason = if flags&32 == 0 {
+              WakeReason::Scheduled
+            } else if triggered_at > 0 {
+              WakeReason::Triggered
+            } else if next_execution_at == 0 {
+              WakeReason::Scheduled
+            } else {
+              WakeReason::Timeout
+            };
+            context::set_wake_reason(reason);
+            context::set_task(task_id(page_id, l2_pos));
+
+            // the delay since the task was triggered, or since it was due
+            if triggered_at > 0 {
+              unsafe { (*wrk).record_delay((now+1).saturating_sub(triggered_at)); }
+            } else if next_execution_at > 0 {
+              unsafe { (*wrk).record_delay(now - next_execution_at); }
             }
+            context::set_skipped_ticks(unsafe { (*wrk).skip_ticks(next_execution_at, now) });
+
+            let mut stop = false;
+            let mut not_restarted = false;
+            private_data.start_execution(task_id(page_id, l2_pos), now);
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+              unsafe {
+                // restart-pending: recreate the task's state first. a
+                // failed task without a factory is not run again.
+                if restart && !(*wrk).restart() && failed {
+                  not_restarted = true;
+                  return;
+                }
+                // flags&1 is the dependents flag
+                (*wrk).execute(task_id(page_id, l2_pos), flags&1 == 1, &mut stop, private_data);
+              }
+            }));
 
+            let stuck = private_data.end_execution();
+            context::clear_task();
+            // the consumers of its outputs that the task added
+            let deps = context::take_dependents();
+            if !deps.is_empty() {
+              unsafe { (*wrk).register_dependents(deps); }
+              (act_data.1).0.fetch_or(1, Ordering::Release);
+              flags |= 1;
+            }
             let atomic_flags = &mut (act_data.1).0;
             let end = time_us.load(Ordering::Acquire);
 
-            if stop {
+            if not_restarted {
+              atomic_flags.fetch_or(8|16, Ordering::Release);
+              private_data.save_not_restarted(task_id(page_id, l2_pos));
+            } else if let Err(payload) = result {
+              // the task panicked: set the failed and the stop bits, but
+              // keep the task in its slot so it can still be removed
+              atomic_flags.fetch_or(8|16, Ordering::Release);
+              private_data.save_failure(task_id(page_id, l2_pos), panic_message(payload));
+            } else if stuck {
+              // the watchdog found it stuck, handled like a panic
+              atomic_flags.fetch_or(8|16, Ordering::Release);
+              private_data.save_failure(task_id(page_id, l2_pos), String::from("stuck in process"));
+            } else if stop {
               // the task said to be stopped, so set the stop bit 
               atomic_flags.fetch_or(16, Ordering::Release);
+              private_data.save_stopped(task_id(page_id, l2_pos));
             } else if flags&32 == 32 {
               // for conditionally executed tasks that:
               // 1, wait for external notification
               // 2, wait for message
-              // -> set exec time to 10s ahead
+              // -> set exec time to the timeout ahead, unless they were
+              //    triggered meanwhile
               // -> add back original flags
-              let new_flags : usize = (end+10_000_000)<<6 | (flags&63);
-              atomic_flags.store(new_flags, Ordering::Release);
+              let timeout = match act_data.2.load(Ordering::Acquire) {
+                0 => default_timeout_us(),
+                t => t,
+              };
+              let at = cmp::min(end.saturating_add(timeout), never());
+              let new_flags : usize = at<<8 | (flags&255);
+              let _ = atomic_flags.fetch_update(Ordering::AcqRel, Ordering::Acquire, |f| {
+                if f>>8 == 0 { None } else { Some(new_flags) }
+              });
+              if at < next_at {
+                next_at = at;
+              }
+              if unsafe { (*wrk).waits_for_fd() } {
+                private_data.save_rearm(task_id(page_id, l2_pos));
+              }
             } else if flags&4 == 4 {
-              // flags&4 is the delay flag. the third component of the
-              // data/act_data is the delay amount: i.e. i.2
-              let new_flags : usize = (now+(act_data.2).0)<<6 | (flags&63);
-              atomic_flags.store(new_flags, Ordering::Release);
+              // flags&4 is the delay flag. the task's rule tells when to
+              // run next, unless it was triggered meanwhile
+              let next = unsafe { (*wrk).next_exec_at(now) };
+              let new_flags : usize = next<<8 | (flags&255);
+              let _ = atomic_flags.fetch_update(Ordering::AcqRel, Ordering::Acquire, |f| {
+                if f>>8 == 0 { None } else { Some(new_flags) }
+              });
+              if next < next_at {
+                next_at = next;
+              }
             }
             now = end;
             act_data.0.store(wrk, Ordering::Release);
@@ -152,6 +648,7 @@ impl TaskPage {
       }
       l2_idx += 1;
     }
+    (executed, next_at)
   }
 
   #[cfg(any(test,feature = "printstats"))]
@@ -166,9 +663,9 @@ impl TaskPage {
         let delayed = flags&4 == 4;
         let stopped = flags&16 == 16;
         let conditional = flags&32 == 32;
-        let next_execution_at = flags>>6;
+        let next_execution_at = flags>>8;
         println!("#{} has-dep:{:?} delayed:{:?}/{:?} stop:{:?} cond:{:?} next:{}",
-          pos, has_dependents, delayed, (i.2).0,
+          pos, has_dependents, delayed, i.2.load(Ordering::Acquire),
           stopped, conditional,
           next_execution_at);
       }
@@ -181,17 +678,19 @@ impl TaskPage {
   fn print_stats(&self) {}
 }
 
-pub fn new(_id: usize) -> TaskPage {
+pub fn new(id: usize) -> TaskPage {
   let sz               = max_idx()+1;
   let mut data         = Vec::with_capacity(sz);
 
   for _i in 0..sz {
     // default flag is stopped: 16
-    let f = ExecFlags(AtomicUsize::new(16));
-    data.push( (AtomicPtr::default(), f, PeriodLengthInUsec(0)) );
+    let f = ExecFlags(AtomicUsize::new(16), AtomicUsize::new(0));
+    data.push( (AtomicPtr::default(), f, AtomicUsize::new(0), AtomicUsize::new(0),
+                 AtomicUsize::new(no_priority_level())) );
   }
 
   TaskPage{
+    id,
     data:   data,
   }
 }
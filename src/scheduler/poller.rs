@@ -0,0 +1,401 @@
+use std::collections::{HashMap};
+use std::os::unix::io::{RawFd};
+use std::sync::{Mutex};
+use super::super::{TaskId, Error, PeriodLengthInUsec};
+use libc;
+use std::mem;
+use std::ptr;
+
+// the epoll data of the stop event
+const STOP_EVENT : u64 = u64::MAX;
+
+// readiness of a file descriptor
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Interest {
+  Readable,
+  Writable,
+}
+
+struct State {
+  // -1 until the first registration
+  epoll_fd:    RawFd,
+  stop_fd:     RawFd,
+  // the registered descriptors are duplicated, so the same one can be
+  // registered by more tasks. the flag is true for the signal and timer
+  // descriptors, which are read by the poller.
+  registered:  HashMap<TaskId, (RawFd, u32, bool)>,
+  // true when the thread that waits for the events is needed
+  start:       bool,
+  // the signal of each OnSignal task, and whether it was blocked on the
+  // registering thread before
+  signals:     HashMap<TaskId, (i32, bool)>,
+  // the signals cannot be blocked on running executors, so no more can
+  // be registered once they are started
+  sealed:      bool,
+}
+
+// waits for the registered descriptors on its own thread. the descriptors
+// of the tasks are registered as oneshot, and re-armed after the task was
+// executed, so the task is not triggered again before it handled the
+// previous event. the signal and timer descriptors are read right away.
+pub struct Poller {
+  state:  Mutex<State>,
+}
+
+fn last_error() -> Error {
+  Error::OsError(::std::io::Error::last_os_error().raw_os_error().unwrap_or(0))
+}
+
+fn unblock_signal(signum: i32) {
+  unsafe {
+    let mut mask : libc::sigset_t = mem::zeroed();
+    libc::sigemptyset(&mut mask);
+    libc::sigaddset(&mut mask, signum);
+    libc::pthread_sigmask(libc::SIG_UNBLOCK, &mask, ptr::null_mut());
+  }
+}
+
+#[cfg(target_os = "linux")]
+fn epoll_events(interest: Interest) -> u32 {
+  let events = match interest {
+    Interest::Readable => libc::EPOLLIN,
+    Interest::Writable => libc::EPOLLOUT,
+  };
+  (events | libc::EPOLLONESHOT) as u32
+}
+
+#[cfg(target_os = "linux")]
+fn epoll_ctl(epoll_fd: RawFd, op: i32, fd: RawFd, events: u32, data: u64) -> Result<(), Error> {
+  let mut event = libc::epoll_event{ events, u64: data };
+  if unsafe { libc::epoll_ctl(epoll_fd, op, fd, &mut event) } < 0 {
+    return Result::Err(last_error());
+  }
+  Ok(())
+}
+
+impl Poller {
+  #[cfg(target_os = "linux")]
+  fn ensure_epoll(state: &mut State) -> Result<(), Error> {
+    if state.epoll_fd >= 0 {
+      return Ok(());
+    }
+    let epoll_fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+    if epoll_fd < 0 {
+      return Result::Err(last_error());
+    }
+    let stop_fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+    if stop_fd < 0 {
+      let error = last_error();
+      unsafe { libc::close(epoll_fd); }
+      return Result::Err(error);
+    }
+    if let Err(e) = epoll_ctl(epoll_fd, libc::EPOLL_CTL_ADD, stop_fd, libc::EPOLLIN as u32, STOP_EVENT) {
+      unsafe {
+        libc::close(stop_fd);
+        libc::close(epoll_fd);
+      }
+      return Result::Err(e);
+    }
+    state.epoll_fd = epoll_fd;
+    state.stop_fd = stop_fd;
+    state.start = true;
+    Ok(())
+  }
+
+  // takes over the descriptor, it is closed on failure too
+  #[cfg(target_os = "linux")]
+  fn add(&self,
+         id: TaskId,
+         fd: RawFd,
+         events: u32,
+         read_events: bool)
+      -> Result<(), Error>
+  {
+    let mut state = self.state.lock().unwrap();
+    let result = match Poller::ensure_epoll(&mut state) {
+      Ok(_)  => epoll_ctl(state.epoll_fd, libc::EPOLL_CTL_ADD, fd, events, id.0 as u64),
+      Err(e) => Result::Err(e),
+    };
+    if result.is_err() {
+      unsafe { libc::close(fd); }
+      return result;
+    }
+    if let Some((old_fd, _, _)) = state.registered.insert(id, (fd, events, read_events)) {
+      let _ = epoll_ctl(state.epoll_fd, libc::EPOLL_CTL_DEL, old_fd, 0, 0);
+      unsafe { libc::close(old_fd); }
+    }
+    Ok(())
+  }
+
+  // the task is triggered when the descriptor becomes ready
+  #[cfg(target_os = "linux")]
+  pub fn register(&self,
+                  id: TaskId,
+                  fd: RawFd,
+                  interest: Interest)
+      -> Result<(), Error>
+  {
+    let dup_fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if dup_fd < 0 {
+      return Result::Err(last_error());
+    }
+    self.add(id, dup_fd, epoll_events(interest), false)
+  }
+
+  // the task is triggered when the signal arrives. the signal is blocked
+  // on the calling thread, and by block_signals() on the scheduler threads.
+  #[cfg(target_os = "linux")]
+  pub fn register_signal(&self,
+                         id: TaskId,
+                         signum: i32)
+      -> Result<(), Error>
+  {
+    if self.state.lock().unwrap().sealed {
+      return Result::Err(Error::Busy);
+    }
+    let mut was_blocked = false;
+    let fd = unsafe {
+      let mut mask : libc::sigset_t = mem::zeroed();
+      let mut old_mask : libc::sigset_t = mem::zeroed();
+      libc::sigemptyset(&mut mask);
+      if libc::sigaddset(&mut mask, signum) < 0 {
+        return Result::Err(last_error());
+      }
+      let result = libc::pthread_sigmask(libc::SIG_BLOCK, &mask, &mut old_mask);
+      if result != 0 {
+        return Result::Err(Error::OsError(result));
+      }
+      if libc::sigismember(&old_mask, signum) == 1 {
+        was_blocked = true;
+      }
+      libc::signalfd(-1, &mask, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC)
+    };
+    if fd < 0 {
+      let error = last_error();
+      if !was_blocked {
+        unblock_signal(signum);
+      }
+      return Result::Err(error);
+    }
+    if let Err(e) = self.add(id, fd, libc::EPOLLIN as u32, true) {
+      if !was_blocked {
+        unblock_signal(signum);
+      }
+      return Result::Err(e);
+    }
+    let mut state = self.state.lock().unwrap();
+    // the tasks of the same signal share the mask
+    let was_blocked = match state.signals.iter().find(|s| *s.0 != id && (s.1).0 == signum) {
+      Some((_, &(_, shared))) => shared,
+      None                    => was_blocked,
+    };
+    if let Some(old) = state.signals.insert(id, (signum, was_blocked)) {
+      Poller::restore_signal(&state, old);
+    }
+    Ok(())
+  }
+
+  // unblocks the signal of a removed task on the calling thread, unless
+  // it was blocked before or another task still waits for it
+  fn restore_signal(state: &State, (signum, was_blocked): (i32, bool)) {
+    if !was_blocked && state.signals.values().all(|s| s.0 != signum) {
+      unblock_signal(signum);
+    }
+  }
+
+  // called before the executors are started
+  pub fn seal_signals(&self) {
+    self.state.lock().unwrap().sealed = true;
+  }
+
+  // blocks the registered signals on the calling thread. the scheduler
+  // threads call it when they start.
+  #[cfg(target_os = "linux")]
+  pub fn block_signals(&self) {
+    let state = self.state.lock().unwrap();
+    if state.signals.is_empty() {
+      return;
+    }
+    unsafe {
+      let mut mask : libc::sigset_t = mem::zeroed();
+      libc::sigemptyset(&mut mask);
+      for &(signum, _) in state.signals.values() {
+        libc::sigaddset(&mut mask, signum);
+      }
+      libc::pthread_sigmask(libc::SIG_BLOCK, &mask, ptr::null_mut());
+    }
+  }
+
+  // the poller thread doesn't run the tasks, so it takes no signals
+  #[cfg(target_os = "linux")]
+  pub fn block_all_signals(&self) {
+    unsafe {
+      let mut mask : libc::sigset_t = mem::zeroed();
+      libc::sigfillset(&mut mask);
+      libc::pthread_sigmask(libc::SIG_BLOCK, &mask, ptr::null_mut());
+    }
+  }
+
+  // the task is triggered periodically by a timerfd
+  #[cfg(target_os = "linux")]
+  pub fn register_timer(&self,
+                        id: TaskId,
+                        period: PeriodLengthInUsec)
+      -> Result<(), Error>
+  {
+    let fd = unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC) };
+    if fd < 0 {
+      return Result::Err(last_error());
+    }
+    // a zero period would disarm the timer
+    let us = if period.0 == 0 { 1 } else { period.0 };
+    let interval = libc::timespec{
+      tv_sec:   (us / 1_000_000) as libc::time_t,
+      tv_nsec:  ((us % 1_000_000) * 1000) as libc::c_long,
+    };
+    let spec = libc::itimerspec{ it_interval: interval, it_value: interval };
+    if unsafe { libc::timerfd_settime(fd, 0, &spec, ptr::null_mut()) } < 0 {
+      let error = last_error();
+      unsafe { libc::close(fd); }
+      return Result::Err(error);
+    }
+    self.add(id, fd, libc::EPOLLIN as u32, true)
+  }
+
+  #[cfg(not(target_os = "linux"))]
+  pub fn register(&self,
+                  _id: TaskId,
+                  _fd: RawFd,
+                  _interest: Interest)
+      -> Result<(), Error>
+  {
+    Result::Err(Error::OsError(libc::ENOSYS))
+  }
+
+  #[cfg(not(target_os = "linux"))]
+  pub fn register_signal(&self,
+                         _id: TaskId,
+                         _signum: i32)
+      -> Result<(), Error>
+  {
+    Result::Err(Error::OsError(libc::ENOSYS))
+  }
+
+  #[cfg(not(target_os = "linux"))]
+  pub fn block_signals(&self) {}
+
+  #[cfg(not(target_os = "linux"))]
+  pub fn block_all_signals(&self) {}
+
+  #[cfg(not(target_os = "linux"))]
+  pub fn register_timer(&self,
+                        _id: TaskId,
+                        _period: PeriodLengthInUsec)
+      -> Result<(), Error>
+  {
+    Result::Err(Error::OsError(libc::ENOSYS))
+  }
+
+  pub fn unregister(&self, id: TaskId) {
+    let mut state = self.state.lock().unwrap();
+    if let Some((fd, _, _)) = state.registered.remove(&id) {
+      // the original descriptor keeps the registration alive, so it
+      // must be deleted before the duplicate is closed
+      #[cfg(target_os = "linux")]
+      let _ = epoll_ctl(state.epoll_fd, libc::EPOLL_CTL_DEL, fd, 0, 0);
+      unsafe { libc::close(fd); }
+    }
+    if let Some(old) = state.signals.remove(&id) {
+      Poller::restore_signal(&state, old);
+    }
+  }
+
+  // enables the oneshot event again after the task was executed
+  #[cfg(target_os = "linux")]
+  pub fn rearm(&self, id: TaskId) {
+    let state = self.state.lock().unwrap();
+    if let Some(&(fd, events, false)) = state.registered.get(&id) {
+      let _ = epoll_ctl(state.epoll_fd, libc::EPOLL_CTL_MOD, fd, events, id.0 as u64);
+    }
+  }
+
+  #[cfg(not(target_os = "linux"))]
+  pub fn rearm(&self, _id: TaskId) {}
+
+  // returns true once after the first registration, when the caller
+  // needs to start the thread that calls wait()
+  pub fn needs_thread(&self) -> bool {
+    let mut state = self.state.lock().unwrap();
+    let start = state.start;
+    state.start = false;
+    start
+  }
+
+  // waits for the ready descriptors and returns their tasks. returns an
+  // empty list when stopped.
+  #[cfg(target_os = "linux")]
+  pub fn wait(&self) -> Vec<TaskId> {
+    let epoll_fd = self.state.lock().unwrap().epoll_fd;
+    let mut events = vec![libc::epoll_event{ events: 0, u64: 0 }; 64];
+    let n = unsafe { libc::epoll_wait(epoll_fd, events.as_mut_ptr(), events.len() as i32, -1) };
+    let mut ready = Vec::with_capacity(if n > 0 { n as usize } else { 0 });
+    for event in events.iter().take(if n > 0 { n as usize } else { 0 }) {
+      let data = event.u64;
+      if data == STOP_EVENT {
+        return Vec::new();
+      }
+      ready.push(TaskId(data as usize));
+    }
+    // consume the signals and timer expirations
+    let state = self.state.lock().unwrap();
+    let mut buf = [0u8; 1024];
+    for id in ready.iter() {
+      if let Some(&(fd, _, true)) = state.registered.get(id) {
+        while unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } > 0 {}
+      }
+    }
+    ready
+  }
+
+  #[cfg(not(target_os = "linux"))]
+  pub fn wait(&self) -> Vec<TaskId> {
+    Vec::new()
+  }
+
+  // releases the thread in wait()
+  pub fn stop(&self) {
+    let state = self.state.lock().unwrap();
+    if state.stop_fd >= 0 {
+      let one : u64 = 1;
+      unsafe { libc::write(state.stop_fd, &one as *const u64 as *const libc::c_void, 8); }
+    }
+  }
+}
+
+impl Drop for Poller {
+  fn drop(&mut self) {
+    let state = self.state.lock().unwrap();
+    for (_, &(fd, _, _)) in state.registered.iter() {
+      unsafe { libc::close(fd); }
+    }
+    if state.epoll_fd >= 0 {
+      unsafe {
+        libc::close(state.stop_fd);
+        libc::close(state.epoll_fd);
+      }
+    }
+  }
+}
+
+pub fn new() -> Poller {
+  Poller{
+    state: Mutex::new(State{
+      epoll_fd:    -1,
+      stop_fd:     -1,
+      registered:  HashMap::new(),
+      start:       false,
+      signals:     HashMap::new(),
+      sealed:      false,
+    }),
+  }
+}
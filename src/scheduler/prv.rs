@@ -1,14 +1,42 @@
 
-use super::super::{TaskId};
+use super::super::{TaskId, ExecBudget};
+use super::trace::{TraceBuffer};
+use super::stats::{ExecutorCounters};
+use std::sync::{Arc};
+use std::time::{Instant};
 
 pub struct Private {
   to_trigger: Vec<TaskId>,
+  failed:     Vec<(TaskId, String)>,
+  stopped:    Vec<TaskId>,
+  // failed tasks that could not be restarted
+  not_restarted:  Vec<TaskId>,
+  rearm:      Vec<TaskId>,
+  busy_ns:    u64,
+  // the tracing state seen by this thread
+  trace:             Option<Arc<TraceBuffer>>,
+  trace_generation:  usize,
+  // the executor thread's counters, for the watchdog
+  counters:          Option<Arc<ExecutorCounters>>,
+  // the scheduler's budget seen by this thread
+  budget:            ExecBudget,
+  budget_generation: usize,
 }
 
 impl Private {
   pub fn new() -> Private {
     Private {
       to_trigger: Vec::with_capacity(10),
+      failed:     Vec::new(),
+      stopped:    Vec::new(),
+      not_restarted:  Vec::new(),
+      rearm:      Vec::new(),
+      busy_ns:    0,
+      trace:             None,
+      trace_generation:  0,
+      counters:          None,
+      budget:            ExecBudget::default(),
+      budget_generation: 0,
     }
   }
 
@@ -26,6 +54,123 @@ impl Private {
     &self.to_trigger
   }
 
+  #[inline]
+  pub fn add_busy_ns(&mut self, ns: u64) {
+    self.busy_ns += ns;
+  }
+
+  pub fn busy_ns(&self) -> u64 {
+    self.busy_ns
+  }
+
+  pub fn save_failure(&mut self,
+                      id: TaskId,
+                      message: String)
+  {
+    self.failed.push((id, message));
+  }
+
+  pub fn take_failures(&mut self) -> Vec<(TaskId, String)> {
+    ::std::mem::take(&mut self.failed)
+  }
+
+  pub fn save_stopped(&mut self,
+                      id: TaskId)
+  {
+    self.stopped.push(id);
+  }
+
+  pub fn take_stopped(&mut self) -> Vec<TaskId> {
+    ::std::mem::take(&mut self.stopped)
+  }
+
+  pub fn save_not_restarted(&mut self,
+                            id: TaskId)
+  {
+    self.not_restarted.push(id);
+  }
+
+  pub fn take_not_restarted(&mut self) -> Vec<TaskId> {
+    ::std::mem::take(&mut self.not_restarted)
+  }
+
+  // the tasks waiting for a file descriptor again
+  pub fn save_rearm(&mut self,
+                    id: TaskId)
+  {
+    self.rearm.push(id);
+  }
+
+  pub fn take_rearm(&mut self) -> Vec<TaskId> {
+    ::std::mem::take(&mut self.rearm)
+  }
+
+  pub fn set_counters(&mut self, counters: Arc<ExecutorCounters>) {
+    self.counters = Some(counters);
+  }
+
+  #[inline]
+  pub fn start_execution(&self, id: TaskId, now: usize) {
+    if let Some(ref counters) = self.counters {
+      counters.start_execution(id, now);
+    }
+  }
+
+  // returns true if the watchdog marked the execution stuck
+  #[inline]
+  pub fn end_execution(&self) -> bool {
+    match self.counters {
+      Some(ref counters) => counters.end_execution(),
+      None               => false,
+    }
+  }
+
+  pub fn trace_generation(&self) -> usize {
+    self.trace_generation
+  }
+
+  pub fn set_trace(&mut self,
+                   generation: usize,
+                   trace: Option<Arc<TraceBuffer>>)
+  {
+    self.trace_generation = generation;
+    self.trace = trace;
+  }
+
+  #[inline]
+  pub fn is_tracing(&self) -> bool {
+    self.trace.is_some()
+  }
+
+  pub fn record_trace(&self,
+                      task: TaskId,
+                      start: Instant,
+                      dur_ns: u64,
+                      advanced: Vec<usize>)
+  {
+    if let Some(ref trace) = self.trace {
+      trace.record(task, start, dur_ns, advanced);
+    }
+  }
+
+  pub fn budget_generation(&self) -> usize {
+    self.budget_generation
+  }
+
+  pub fn set_budget(&mut self,
+                    generation: usize,
+                    budget: ExecBudget)
+  {
+    self.budget_generation = generation;
+    self.budget = budget;
+  }
+
+  // for the tasks without their own budget
+  #[inline]
+  pub fn budget(&self) -> ExecBudget {
+    self.budget
+  }
+
   pub fn clear(&mut self) {
     self.to_trigger.clear();
   }
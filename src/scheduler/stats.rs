@@ -0,0 +1,269 @@
+use std::sync::atomic::{AtomicUsize, AtomicU64, AtomicBool, Ordering};
+use std::sync::{Mutex};
+use std::time::{Instant, Duration};
+use super::super::{ExecutorStats, TaskId, TaskStats, SchedulingRule, ChannelPosition,
+  ChannelId, SenderName, ExecBudget};
+use super::histogram;
+
+// set in the running task by the watchdog, so it is marked failed
+const STUCK_BIT : usize = 1 << (usize::BITS - 1);
+
+// written by one executor thread only, read by the stats snapshot
+pub struct ExecutorCounters {
+  id:               usize,
+  start:            Instant,
+  loop_count:       AtomicUsize,
+  busy_loop_count:  AtomicUsize,
+  busy_ns:          AtomicUsize,
+  // non-zero after the executor exited
+  elapsed_ns:       AtomicUsize,
+  // the task being executed plus one, or zero, and when it started
+  running:          AtomicUsize,
+  running_since:    AtomicUsize,
+  thread_name:      Mutex<Option<String>>,
+}
+
+fn elapsed_ns(start: &Instant) -> usize {
+  let diff = start.elapsed();
+  diff.as_secs() as usize * 1_000_000_000 + diff.subsec_nanos() as usize
+}
+
+impl ExecutorCounters {
+  #[inline]
+  pub fn update(&self,
+                loop_count: u64,
+                busy_loop_count: u64,
+                busy_ns: u64)
+  {
+    self.loop_count.store(loop_count as usize, Ordering::Relaxed);
+    self.busy_loop_count.store(busy_loop_count as usize, Ordering::Relaxed);
+    self.busy_ns.store(busy_ns as usize, Ordering::Relaxed);
+  }
+
+  pub fn id(&self) -> usize {
+    self.id
+  }
+
+  pub fn loop_count(&self) -> usize {
+    self.loop_count.load(Ordering::Relaxed)
+  }
+
+  pub fn set_thread_name(&self, name: Option<String>) {
+    *self.thread_name.lock().unwrap() = name;
+  }
+
+  pub fn thread_name(&self) -> Option<String> {
+    self.thread_name.lock().unwrap().clone()
+  }
+
+  #[inline]
+  pub fn start_execution(&self, id: TaskId, now: usize) {
+    self.running_since.store(now, Ordering::Release);
+    self.running.store(id.0 + 1, Ordering::Release);
+  }
+
+  // returns true if the watchdog marked the execution stuck
+  #[inline]
+  pub fn end_execution(&self) -> bool {
+    self.running.swap(0, Ordering::AcqRel) & STUCK_BIT != 0
+  }
+
+  // the running task and the start of its execution
+  pub fn running(&self) -> Option<(TaskId, usize)> {
+    let since = self.running_since.load(Ordering::Acquire);
+    match self.running.load(Ordering::Acquire) & !STUCK_BIT {
+      0  => None,
+      id => Some((TaskId(id-1), since)),
+    }
+  }
+
+  // the execution will be marked failed when it returns. false if the
+  // execution ended meanwhile.
+  pub fn mark_stuck(&self, id: TaskId, since: usize) -> bool {
+    if self.running.compare_exchange(id.0 + 1, (id.0 + 1) | STUCK_BIT,
+                                     Ordering::AcqRel, Ordering::Acquire).is_err() {
+      return false;
+    }
+    if self.running_since.load(Ordering::Acquire) != since {
+      // a new execution of the same task
+      let _ = self.running.compare_exchange((id.0 + 1) | STUCK_BIT, id.0 + 1,
+                                            Ordering::AcqRel, Ordering::Acquire);
+      return false;
+    }
+    true
+  }
+
+  pub fn has_exited(&self) -> bool {
+    self.elapsed_ns.load(Ordering::Acquire) != 0
+  }
+
+  pub fn exited(&self) {
+    self.elapsed_ns.store(elapsed_ns(&self.start), Ordering::Release);
+  }
+
+  pub fn snapshot(&self) -> ExecutorStats {
+    let elapsed = match self.elapsed_ns.load(Ordering::Acquire) {
+      0 => elapsed_ns(&self.start),
+      ns => ns,
+    };
+    ExecutorStats{
+      id:               self.id,
+      loop_count:       self.loop_count.load(Ordering::Relaxed) as u64,
+      busy_loop_count:  self.busy_loop_count.load(Ordering::Relaxed) as u64,
+      busy_ns:          self.busy_ns.load(Ordering::Relaxed) as u64,
+      elapsed_ns:       elapsed as u64,
+    }
+  }
+}
+
+pub fn new(id: usize) -> ExecutorCounters {
+  ExecutorCounters{
+    id,
+    start:            Instant::now(),
+    loop_count:       AtomicUsize::new(0),
+    busy_loop_count:  AtomicUsize::new(0),
+    busy_ns:          AtomicUsize::new(0),
+    elapsed_ns:       AtomicUsize::new(0),
+    running:          AtomicUsize::new(0),
+    running_since:    AtomicUsize::new(0),
+    thread_name:      Mutex::new(None),
+  }
+}
+
+// written by the executor that runs the task, read by the stats snapshot
+// and the registry without waiting for the task
+pub struct TaskCounters {
+  name:             String,
+  // the inputs don't change while the task is in the scheduler
+  inputs:           Vec<(ChannelId, SenderName)>,
+  start:            Instant,
+  exec_count:       AtomicU64,
+  total_exec_ns:    AtomicU64,
+  max_exec_ns:      AtomicU64,
+  // the start of the last execution since start plus one, or zero
+  last_run_ns:      AtomicU64,
+  skipped_ticks:    AtomicU64,
+  exec_histogram:   histogram::Histogram,
+  delay_histogram:  histogram::Histogram,
+  rule:             Mutex<SchedulingRule>,
+  // the channel positions when the task was last seen idle
+  positions:        Mutex<(Vec<ChannelPosition>, Vec<ChannelPosition>)>,
+  // the budget set for the task, taken over before its next execution
+  budget:           Mutex<Option<ExecBudget>>,
+  budget_changed:   AtomicBool,
+}
+
+// one executor runs the task at a time, so the counters are not raced
+fn add(counter: &AtomicU64, value: u64) {
+  counter.store(counter.load(Ordering::Relaxed) + value, Ordering::Relaxed);
+}
+
+impl TaskCounters {
+  #[inline]
+  pub fn record_execution(&self, start: Instant, diff_ns: u64) {
+    add(&self.exec_count, 1);
+    add(&self.total_exec_ns, diff_ns);
+    if diff_ns > self.max_exec_ns.load(Ordering::Relaxed) {
+      self.max_exec_ns.store(diff_ns, Ordering::Relaxed);
+    }
+    let since = start.saturating_duration_since(self.start);
+    let since_ns = since.as_secs() * 1_000_000_000 + since.subsec_nanos() as u64;
+    self.last_run_ns.store(since_ns + 1, Ordering::Release);
+    self.exec_histogram.record(diff_ns);
+  }
+
+  pub fn record_delay(&self, delay_ns: u64) {
+    self.delay_histogram.record(delay_ns);
+  }
+
+  pub fn add_skipped_ticks(&self, skipped: u64) {
+    add(&self.skipped_ticks, skipped);
+  }
+
+  pub fn reset_latency(&self) {
+    self.exec_histogram.reset();
+    self.delay_histogram.reset();
+  }
+
+  pub fn inputs(&self) -> &Vec<(ChannelId, SenderName)> {
+    &self.inputs
+  }
+
+  pub fn rule(&self) -> SchedulingRule {
+    *self.rule.lock().unwrap()
+  }
+
+  pub fn set_rule(&self, rule: SchedulingRule) {
+    *self.rule.lock().unwrap() = rule;
+  }
+
+  pub fn set_positions(&self,
+                       inputs: Vec<ChannelPosition>,
+                       outputs: Vec<ChannelPosition>)
+  {
+    *self.positions.lock().unwrap() = (inputs, outputs);
+  }
+
+  // None makes the task use the scheduler's budget again
+  pub fn set_budget(&self, budget: Option<ExecBudget>) {
+    *self.budget.lock().unwrap() = budget;
+    self.budget_changed.store(true, Ordering::Release);
+  }
+
+  // the budget set since the last call, if any
+  #[inline]
+  pub fn take_budget(&self) -> Option<Option<ExecBudget>> {
+    if !self.budget_changed.load(Ordering::Acquire) {
+      return None;
+    }
+    let budget = self.budget.lock().unwrap();
+    self.budget_changed.store(false, Ordering::Release);
+    Some(*budget)
+  }
+
+  pub fn snapshot(&self, id: TaskId) -> TaskStats {
+    let (input_positions, output_positions) = self.positions.lock().unwrap().clone();
+    let last_run = match self.last_run_ns.load(Ordering::Acquire) {
+      0  => None,
+      ns => Some(self.start + Duration::from_nanos(ns-1)),
+    };
+    TaskStats{
+      name:              self.name.clone(),
+      id,
+      rule:              self.rule(),
+      exec_count:        self.exec_count.load(Ordering::Relaxed),
+      total_exec_ns:     self.total_exec_ns.load(Ordering::Relaxed),
+      max_exec_ns:       self.max_exec_ns.load(Ordering::Relaxed),
+      last_run,
+      skipped_ticks:     self.skipped_ticks.load(Ordering::Relaxed),
+      input_positions,
+      output_positions,
+      exec_latency:      self.exec_histogram.percentiles(),
+      delay_latency:     self.delay_histogram.percentiles(),
+    }
+  }
+}
+
+pub fn new_task(name: String,
+                inputs: Vec<(ChannelId, SenderName)>,
+                rule: SchedulingRule,
+                positions: (Vec<ChannelPosition>, Vec<ChannelPosition>))
+    -> TaskCounters
+{
+  TaskCounters{
+    name,
+    inputs,
+    start:            Instant::now(),
+    exec_count:       AtomicU64::new(0),
+    total_exec_ns:    AtomicU64::new(0),
+    max_exec_ns:      AtomicU64::new(0),
+    last_run_ns:      AtomicU64::new(0),
+    skipped_ticks:    AtomicU64::new(0),
+    exec_histogram:   histogram::new(),
+    delay_histogram:  histogram::new(),
+    rule:             Mutex::new(rule),
+    positions:        Mutex::new(positions),
+    budget:           Mutex::new(None),
+    budget_changed:   AtomicBool::new(false),
+  }
+}
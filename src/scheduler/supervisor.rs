@@ -0,0 +1,124 @@
+use std::collections::{VecDeque};
+use std::sync::{Mutex};
+use std::cmp;
+use super::super::{TaskId, RestartPolicy, SupervisorStrategy, RestartIntensity,
+  PeriodLengthInUsec};
+
+struct Unit {
+  strategy:  SupervisorStrategy,
+  policy:    RestartPolicy,
+  members:   Vec<TaskId>,
+  // the time of the restarts within the restart window
+  restarts:  VecDeque<usize>,
+}
+
+pub struct Supervisor {
+  units:  Mutex<Vec<Unit>>,
+}
+
+impl Supervisor {
+  // a task belongs to at most one unit, so the members are taken out of
+  // their previous units
+  pub fn add(&self,
+             strategy: SupervisorStrategy,
+             policy: RestartPolicy,
+             members: Vec<TaskId>)
+  {
+    let mut units = self.units.lock().unwrap();
+    for unit in units.iter_mut() {
+      unit.members.retain(|id| !members.contains(id));
+    }
+    units.retain(|unit| !unit.members.is_empty());
+    units.push(Unit{
+      strategy,
+      policy,
+      members,
+      restarts:  VecDeque::new(),
+    });
+  }
+
+  pub fn remove(&self, id: TaskId) {
+    let mut units = self.units.lock().unwrap();
+    for unit in units.iter_mut() {
+      unit.members.retain(|member| *member != id);
+    }
+    units.retain(|unit| !unit.members.is_empty());
+  }
+
+  // decides what to do when a task stopped or failed. returns the tasks
+  // to be restarted and the time of the restart.
+  pub fn on_exit(&self,
+                 id: TaskId,
+                 failed: bool,
+                 now: usize) -> Option<(Vec<TaskId>, usize)>
+  {
+    let mut units = self.units.lock().unwrap();
+    let unit = match units.iter_mut().find(|unit| unit.members.contains(&id)) {
+      Some(unit) => unit,
+      None       => { return None; }
+    };
+
+    let restart_at = match unit.policy {
+      RestartPolicy::Never => { return None; },
+      RestartPolicy::Always => {
+        // a task that exits right away is not restarted in a busy loop
+        let intensity = always_intensity();
+        let at = restart_at(&mut unit.restarts, intensity, now);
+        cmp::min(at, now + intensity.window.0)
+      },
+      RestartPolicy::OnFailure(intensity) => {
+        if !failed {
+          return None;
+        }
+        forget_restarts(&mut unit.restarts, intensity, now);
+        if unit.restarts.len() >= intensity.max_restarts {
+          // too many restarts in the window, give up
+          return None;
+        }
+        restart_at(&mut unit.restarts, intensity, now)
+      },
+    };
+
+    match unit.strategy {
+      SupervisorStrategy::OneForOne => Some((vec![id], restart_at)),
+      SupervisorStrategy::OneForAll => Some((unit.members.clone(), restart_at)),
+    }
+  }
+}
+
+// the backoff of the Always policy, at most the window
+fn always_intensity() -> RestartIntensity {
+  RestartIntensity{
+    max_restarts:  usize::MAX,
+    window:        PeriodLengthInUsec(1_000_000),
+    backoff:       PeriodLengthInUsec(1_000),
+  }
+}
+
+fn forget_restarts(restarts: &mut VecDeque<usize>,
+                   intensity: RestartIntensity,
+                   now: usize)
+{
+  while let Some(&at) = restarts.front() {
+    if at + intensity.window.0 > now { break; }
+    restarts.pop_front();
+  }
+}
+
+// records the restart. the backoff doubles with each restart in the
+// window.
+fn restart_at(restarts: &mut VecDeque<usize>,
+              intensity: RestartIntensity,
+              now: usize) -> usize
+{
+  forget_restarts(restarts, intensity, now);
+  let shift = cmp::min(restarts.len(), 16);
+  restarts.push_back(now);
+  now + (intensity.backoff.0 << shift)
+}
+
+pub fn new() -> Supervisor {
+  Supervisor{
+    units:  Mutex::new(Vec::new()),
+  }
+}
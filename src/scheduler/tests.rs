@@ -0,0 +1,1654 @@
+use lossyq::spsc::Sender;
+use super::super::elem::{source, sink, stream};
+use super::super::elem::connectable::{Connectable};
+use super::super::{Task, Error, Message, ChannelWrapper, SchedulingRule, PeriodLengthInUsec,
+  IdleStrategy, IdleSpinCount, RestartPolicy, RestartIntensity, ThreadConfig,
+  TaskAffinity, Priority, WakeReason, PeriodicTimer, LatencyPercentiles, WatchdogAction,
+  ExecBudget, TaskState, StuckTask, TaskId, ChannelId, SenderChannelId, ReceiverChannelId,
+  SenderName};
+use super::{Scheduler, wake_reason, skipped_ticks, budget, budget_expired};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Condvar};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::future::{Future};
+use std::pin::{Pin};
+use std::task::{Context, Poll, Wake, Waker};
+use libc;
+
+struct CountingSource {
+  count: Arc<AtomicUsize>,
+}
+
+impl source::Source for CountingSource {
+  type OutputValue = usize;
+  type OutputError = &'static str;
+
+  fn process(&mut self,
+             output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             _stop: &mut bool)
+  {
+    let count = self.count.fetch_add(1, Ordering::AcqRel);
+    output.put(|v| *v = Some(Message::Value(count)));
+  }
+}
+
+struct PanickingSource {}
+
+impl source::Source for PanickingSource {
+  type OutputValue = usize;
+  type OutputError = &'static str;
+
+  fn process(&mut self,
+             _output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             _stop: &mut bool)
+  {
+    panic!("bad input");
+  }
+}
+
+// counts the executions on threads with other names than expected
+struct ThreadCheckingSource {
+  expected: String,
+  count:    Arc<AtomicUsize>,
+  wrong:    Arc<AtomicUsize>,
+}
+
+impl source::Source for ThreadCheckingSource {
+  type OutputValue = usize;
+  type OutputError = &'static str;
+
+  fn process(&mut self,
+             _output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             _stop: &mut bool)
+  {
+    if thread::current().name() != Some(self.expected.as_str()) {
+      self.wrong.fetch_add(1, Ordering::AcqRel);
+    }
+    self.count.fetch_add(1, Ordering::AcqRel);
+  }
+}
+
+// records why it was executed
+struct WakeReasonSource {
+  reasons: Arc<Mutex<Vec<WakeReason>>>,
+}
+
+impl source::Source for WakeReasonSource {
+  type OutputValue = usize;
+  type OutputError = &'static str;
+
+  fn process(&mut self,
+             _output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             _stop: &mut bool)
+  {
+    self.reasons.lock().unwrap().push(wake_reason());
+  }
+}
+
+// the first execution takes longer than the period
+struct SlowStartSource {
+  count:    Arc<AtomicUsize>,
+  skipped:  Arc<AtomicUsize>,
+}
+
+impl source::Source for SlowStartSource {
+  type OutputValue = usize;
+  type OutputError = &'static str;
+
+  fn process(&mut self,
+             _output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             _stop: &mut bool)
+  {
+    if self.count.fetch_add(1, Ordering::AcqRel) == 0 {
+      unsafe { libc::usleep(5_500); }
+    }
+    self.skipped.fetch_add(skipped_ticks(), Ordering::AcqRel);
+  }
+}
+
+// reads everything from a non-blocking descriptor
+struct ReadingSource {
+  fd:     libc::c_int,
+  count:  Arc<AtomicUsize>,
+  bytes:  Arc<AtomicUsize>,
+}
+
+impl source::Source for ReadingSource {
+  type OutputValue = usize;
+  type OutputError = &'static str;
+
+  fn process(&mut self,
+             _output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             _stop: &mut bool)
+  {
+    self.count.fetch_add(1, Ordering::AcqRel);
+    let mut buf = [0u8; 64];
+    loop {
+      let n = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+      if n <= 0 { break; }
+      self.bytes.fetch_add(n as usize, Ordering::AcqRel);
+    }
+  }
+}
+
+// remembers the last value received and counts the shutdown calls
+struct LastValueSink {
+  last:      Arc<AtomicUsize>,
+  shutdown:  Arc<AtomicUsize>,
+}
+
+impl sink::Sink for LastValueSink {
+  type InputValue = usize;
+  type InputError = &'static str;
+
+  fn process(&mut self,
+             input: &mut ChannelWrapper<Self::InputValue, Self::InputError>,
+             _stop: &mut bool)
+  {
+    if let &mut ChannelWrapper::ConnectedReceiver(_, ref mut receiver, _) = input {
+      for m in receiver.iter() {
+        if let Message::Value(v) = m {
+          self.last.store(v+1, Ordering::Release);
+        }
+      }
+    }
+  }
+
+  fn shutdown(&mut self) {
+    self.shutdown.fetch_add(1, Ordering::AcqRel);
+  }
+}
+
+fn counting_source(name: &str) -> (Box<dyn Task+Send>, Arc<AtomicUsize>) {
+  let count = Arc::new(AtomicUsize::new(0));
+  let (task, _out) = source::new(name, 10, Box::new(CountingSource{ count: count.clone() }));
+  (task, count)
+}
+
+fn wait_for(count: &Arc<AtomicUsize>, at_least: usize) -> bool {
+  for _i in 0..2000 {
+    if count.load(Ordering::Acquire) >= at_least {
+      return true;
+    }
+    unsafe { libc::usleep(1000); }
+  }
+  false
+}
+
+#[test]
+fn parked_executor_wakes_on_notify() {
+  let mut sched = Scheduler::new();
+  assert!(sched.set_idle_strategy(IdleStrategy::SpinThenPark(IdleSpinCount(10))).is_ok());
+  sched.start_with_threads(2);
+  assert!(sched.set_idle_strategy(IdleStrategy::Spin).is_err());
+
+  let (task, count) = counting_source("Source");
+  let id = sched.add_task(task, SchedulingRule::OnExternalEvent).unwrap();
+
+  // give the executors time to park
+  unsafe { libc::usleep(20_000); }
+  let before = count.load(Ordering::Acquire);
+  assert!(sched.notify(&id).is_ok());
+  assert!(wait_for(&count, before+1));
+  sched.stop();
+}
+
+#[test]
+fn parked_executor_wakes_for_periodic() {
+  let mut sched = Scheduler::new();
+  assert!(sched.set_idle_strategy(IdleStrategy::SpinThenPark(IdleSpinCount(10))).is_ok());
+  sched.start();
+
+  let (task, count) = counting_source("Periodic");
+  sched.add_task(task, SchedulingRule::Periodic(PeriodLengthInUsec(1000))).unwrap();
+  assert!(wait_for(&count, 5));
+  sched.stop();
+}
+
+#[test]
+fn remove_and_readd_task() {
+  let mut sched = Scheduler::new();
+  sched.start();
+
+  let (task, count) = counting_source("Source");
+  let id = sched.add_task(task, SchedulingRule::Loop).unwrap();
+  assert!(wait_for(&count, 1));
+
+  let removed = sched.remove_task(&id).unwrap();
+  assert_eq!(removed.name(), "Source");
+  assert!(sched.remove_task(&id).is_err());
+
+  // the task doesn't run anymore and its name can be reused
+  let after_remove = count.load(Ordering::Acquire);
+  unsafe { libc::usleep(10_000); }
+  assert_eq!(count.load(Ordering::Acquire), after_remove);
+  let new_id = sched.add_task(removed, SchedulingRule::Loop).unwrap();
+  assert!(new_id != id);
+  assert!(wait_for(&count, after_remove+1));
+  sched.stop();
+}
+
+#[test]
+fn removed_task_is_not_waited_for() {
+  let mut sched = Scheduler::new();
+  sched.start_with_threads(2);
+  let handle = sched.handle();
+
+  // looks up the tasks while they are removed
+  let done = Arc::new(AtomicUsize::new(0));
+  let reader = {
+    let done = done.clone();
+    thread::spawn(move || {
+      while done.load(Ordering::Acquire) == 0 {
+        handle.stats();
+        handle.tasks();
+      }
+    })
+  };
+  for _i in 0..200 {
+    let (task, _count) = counting_source("Source");
+    let id = sched.add_task(task, SchedulingRule::Loop).unwrap();
+    assert!(sched.remove_task(&id).is_ok());
+    assert!(sched.pause(&id).is_err());
+    assert!(sched.set_rule(&id, SchedulingRule::Loop).is_err());
+  }
+  done.store(1, Ordering::Release);
+  assert!(reader.join().is_ok());
+  sched.stop();
+}
+
+#[test]
+fn panicking_task_is_isolated() {
+  let mut sched = Scheduler::new();
+  sched.start_with_threads(2);
+
+  let (bad_task, _bad_out) = source::new("Bad", 10, Box::new(PanickingSource{}));
+  let bad_id = sched.add_task(bad_task, SchedulingRule::Loop).unwrap();
+  let (task, count) = counting_source("Good");
+  sched.add_task(task, SchedulingRule::Loop).unwrap();
+
+  for _i in 0..2000 {
+    if !sched.failed_tasks().is_empty() { break; }
+    unsafe { libc::usleep(1000); }
+  }
+  let failed = sched.failed_tasks();
+  assert_eq!(failed.len(), 1);
+  assert_eq!(failed[0].0, bad_id);
+  assert_eq!(failed[0].1, "bad input");
+
+  // the other task keeps running
+  let before = count.load(Ordering::Acquire);
+  assert!(wait_for(&count, before+10));
+
+  let bad_task = sched.remove_task(&bad_id).unwrap();
+  assert_eq!(bad_task.name(), "Bad");
+  assert!(sched.failed_tasks().is_empty());
+  sched.stop();
+}
+
+#[test]
+fn failed_task_restarted_until_intensity_reached() {
+  let mut sched = Scheduler::new();
+  sched.start();
+
+  let created = Arc::new(AtomicUsize::new(0));
+  let (mut bad_task, _bad_out) = source::new("Bad", 10, Box::new(PanickingSource{}));
+  {
+    let created = created.clone();
+    bad_task.set_factory(Box::new(move || {
+      created.fetch_add(1, Ordering::AcqRel);
+      Box::new(PanickingSource{})
+    }));
+  }
+  let bad_id = sched.add_task(bad_task, SchedulingRule::Loop).unwrap();
+  let intensity = RestartIntensity{
+    max_restarts:  3,
+    window:        PeriodLengthInUsec(60_000_000),
+    backoff:       PeriodLengthInUsec(100),
+  };
+  assert!(sched.set_restart_policy(&bad_id, RestartPolicy::OnFailure(intensity)).is_ok());
+
+  // the first run fails, then it is restarted three times
+  assert!(wait_for(&created, 3));
+  for _i in 0..2000 {
+    if !sched.failed_tasks().is_empty() { break; }
+    unsafe { libc::usleep(1000); }
+  }
+  unsafe { libc::usleep(10_000); }
+  assert_eq!(created.load(Ordering::Acquire), 3);
+  assert_eq!(sched.failed_tasks().len(), 1);
+  sched.stop();
+}
+
+// counts its executions before it panics
+struct CountingPanicSource {
+  count: Arc<AtomicUsize>,
+}
+
+impl source::Source for CountingPanicSource {
+  type OutputValue = usize;
+  type OutputError = &'static str;
+
+  fn process(&mut self,
+             _output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             _stop: &mut bool)
+  {
+    self.count.fetch_add(1, Ordering::AcqRel);
+    panic!("bad input");
+  }
+}
+
+#[test]
+fn failed_task_without_factory_stays_failed() {
+  let mut sched = Scheduler::new_with_virtual_time();
+
+  let count = Arc::new(AtomicUsize::new(0));
+  let (task, _out) = source::new("Bad", 10, Box::new(CountingPanicSource{ count: count.clone() }));
+  let id = sched.add_task(task, SchedulingRule::OnExternalEvent).unwrap();
+  assert!(sched.set_restart_policy(&id, RestartPolicy::Always).is_ok());
+
+  // the restart is due after the backoff
+  for _i in 0..5 {
+    sched.step();
+    sched.advance_time(PeriodLengthInUsec(1000));
+  }
+  assert_eq!(count.load(Ordering::Acquire), 1);
+  let failed = sched.failed_tasks();
+  assert_eq!(failed.len(), 1);
+  assert_eq!(failed[0].0, id);
+}
+
+// counts its executions and stops each time
+struct StoppingSource {
+  count: Arc<AtomicUsize>,
+}
+
+impl source::Source for StoppingSource {
+  type OutputValue = usize;
+  type OutputError = &'static str;
+
+  fn process(&mut self,
+             _output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             stop: &mut bool)
+  {
+    self.count.fetch_add(1, Ordering::AcqRel);
+    *stop = true;
+  }
+}
+
+#[test]
+fn always_restarted_task_backs_off() {
+  let mut sched = Scheduler::new_with_virtual_time();
+  let count = Arc::new(AtomicUsize::new(0));
+  let (task, _out) = source::new("Stopping", 10, Box::new(StoppingSource{ count: count.clone() }));
+  let id = sched.add_task(task, SchedulingRule::Loop).unwrap();
+  assert!(sched.set_restart_policy(&id, RestartPolicy::Always).is_ok());
+
+  // the delay doubles with each restart
+  sched.run_until_idle();
+  assert_eq!(count.load(Ordering::Acquire), 1);
+  sched.advance_time(PeriodLengthInUsec(999));
+  sched.run_until_idle();
+  assert_eq!(count.load(Ordering::Acquire), 1);
+  sched.advance_time(PeriodLengthInUsec(1));
+  sched.run_until_idle();
+  assert_eq!(count.load(Ordering::Acquire), 2);
+  sched.advance_time(PeriodLengthInUsec(1999));
+  sched.run_until_idle();
+  assert_eq!(count.load(Ordering::Acquire), 2);
+  sched.advance_time(PeriodLengthInUsec(1));
+  sched.run_until_idle();
+  assert_eq!(count.load(Ordering::Acquire), 3);
+}
+
+#[test]
+fn stats_snapshot() {
+  let mut sched = Scheduler::new();
+  sched.start_with_threads(2);
+
+  let (task, count) = counting_source("Source");
+  let id = sched.add_task(task, SchedulingRule::Loop).unwrap();
+  assert!(wait_for(&count, 100));
+
+  let stats = sched.stats();
+  assert_eq!(stats.tasks.len(), 1);
+  assert_eq!(stats.tasks[0].name, "Source");
+  assert_eq!(stats.tasks[0].id, id);
+  assert!(stats.tasks[0].exec_count >= 100);
+  assert!(stats.tasks[0].last_run.is_some());
+  assert_eq!(stats.tasks[0].input_positions.len(), 0);
+  assert!(stats.tasks[0].output_positions[0].0 as u64 >= stats.tasks[0].exec_count);
+  assert_eq!(stats.executors.len(), 2);
+  sched.stop();
+}
+
+#[test]
+fn start_with_pinned_threads() {
+  let mut sched = Scheduler::new();
+
+  // an invalid CPU fails and no thread is started
+  let mut invalid = ThreadConfig::new();
+  invalid.cpus = vec![1_000_000];
+  assert!(sched.start_with_thread_config(vec![invalid], ThreadConfig::new()).is_err());
+
+  let mut pinned = ThreadConfig::new();
+  pinned.name = Some(String::from("executor-0"));
+  pinned.cpus = vec![0];
+  assert!(sched.start_with_thread_config(vec![pinned], ThreadConfig::new()).is_ok());
+
+  let (task, count) = counting_source("Source");
+  sched.add_task(task, SchedulingRule::Loop).unwrap();
+  assert!(wait_for(&count, 10));
+  sched.stop();
+}
+
+#[test]
+fn pinned_tasks_run_on_their_executor() {
+  let mut sched = Scheduler::new();
+  let mut configs = vec![ThreadConfig::new(); 3];
+  for (i, config) in configs.iter_mut().enumerate() {
+    config.name = Some(format!("executor-{}", i));
+  }
+  configs[2].group = Some(String::from("io"));
+  assert!(sched.start_with_thread_config(configs, ThreadConfig::new()).is_ok());
+
+  let wrong = Arc::new(AtomicUsize::new(0));
+  let by_index = Arc::new(AtomicUsize::new(0));
+  let by_group = Arc::new(AtomicUsize::new(0));
+  let (task, _out) = source::new("ByIndex", 10, Box::new(ThreadCheckingSource{
+    expected: String::from("executor-1"), count: by_index.clone(), wrong: wrong.clone() }));
+  sched.add_task_with_affinity(task, SchedulingRule::Loop, TaskAffinity::Executor(1)).unwrap();
+  let (task, _out) = source::new("ByGroup", 10, Box::new(ThreadCheckingSource{
+    expected: String::from("executor-2"), count: by_group.clone(), wrong: wrong.clone() }));
+  sched.add_task_with_affinity(task, SchedulingRule::Loop, TaskAffinity::Group(String::from("io"))).unwrap();
+
+  assert!(wait_for(&by_index, 1000));
+  assert!(wait_for(&by_group, 1000));
+  assert_eq!(wrong.load(Ordering::Acquire), 0);
+  sched.stop();
+}
+
+#[test]
+fn low_priority_task_not_starved() {
+  let mut sched = Scheduler::new_with_virtual_time();
+  sched.set_starvation_limit(9);
+
+  let (low_task, low_count) = counting_source("Low");
+  let low_id = sched.add_task(low_task, SchedulingRule::Loop).unwrap();
+  let (high_task, high_count) = counting_source("High");
+  let high_id = sched.add_task(high_task, SchedulingRule::Loop).unwrap();
+  assert!(sched.set_priority(&low_id, Priority::Low).is_ok());
+  assert!(sched.set_priority(&high_id, Priority::High).is_ok());
+
+  // the low priority task runs in every 10th pass
+  for _i in 0..100 {
+    sched.step();
+  }
+  assert_eq!(high_count.load(Ordering::Acquire), 100);
+  assert_eq!(low_count.load(Ordering::Acquire), 10);
+}
+
+#[test]
+fn task_added_with_priority() {
+  let mut sched = Scheduler::new_with_virtual_time();
+  sched.set_starvation_limit(9);
+
+  let (low_task, low_count) = counting_source("Low");
+  let low_id = sched.add_task_with_priority(low_task, SchedulingRule::Loop, Priority::Low).unwrap();
+  let (high_task, high_count) = counting_source("High");
+  sched.add_task_with_priority(high_task, SchedulingRule::Loop, Priority::High).unwrap();
+
+  // the low priority task never ran on the normal level
+  sched.step();
+  assert_eq!(high_count.load(Ordering::Acquire), 1);
+  assert_eq!(low_count.load(Ordering::Acquire), 0);
+  for _i in 1..100 {
+    sched.step();
+  }
+  assert_eq!(high_count.load(Ordering::Acquire), 100);
+  assert_eq!(low_count.load(Ordering::Acquire), 10);
+
+  // the level of a removed task can't be changed
+  assert!(sched.remove_task(&low_id).is_ok());
+  assert!(sched.set_priority(&low_id, Priority::High).is_err());
+}
+
+#[test]
+fn shutdown_drains_pipeline() {
+  let mut sched = Scheduler::new();
+  sched.start_with_threads(2);
+
+  let count = Arc::new(AtomicUsize::new(0));
+  let last = Arc::new(AtomicUsize::new(0));
+  let shutdown = Arc::new(AtomicUsize::new(0));
+  let (source_task, mut source_out) = source::new("Source", 10, Box::new(CountingSource{ count: count.clone() }));
+  let mut sink_task = sink::new("Sink", Box::new(LastValueSink{ last: last.clone(), shutdown: shutdown.clone() }));
+  assert!(sink_task.connect(&mut source_out).is_ok());
+  let source_id = sched.add_task(source_task, SchedulingRule::Loop).unwrap();
+  let sink_id = sched.add_task(sink_task, SchedulingRule::OnMessage).unwrap();
+  assert!(wait_for(&last, 100));
+
+  let report = sched.shutdown(Duration::from_secs(5));
+  assert!(!report.timed_out);
+  assert_eq!(report.sources, vec![(source_id, String::from("Source"))]);
+  assert_eq!(report.drained, vec![(sink_id, String::from("Sink"))]);
+  assert!(report.dropped.is_empty());
+  // the sink received the last message of the source
+  assert_eq!(last.load(Ordering::Acquire), count.load(Ordering::Acquire));
+  assert_eq!(shutdown.load(Ordering::Acquire), 1);
+}
+
+#[test]
+fn pause_and_resume() {
+  let mut sched = Scheduler::new();
+  sched.start_with_threads(2);
+
+  let (task, count) = counting_source("Source");
+  let id = sched.add_task(task, SchedulingRule::Loop).unwrap();
+  assert!(wait_for(&count, 1));
+
+  // a paused task is not executed
+  assert!(sched.pause(&id).is_ok());
+  let paused_at = count.load(Ordering::Acquire);
+  unsafe { libc::usleep(10_000); }
+  assert_eq!(count.load(Ordering::Acquire), paused_at);
+  assert!(sched.resume(&id).is_ok());
+  assert!(wait_for(&count, paused_at+1));
+
+  // neither is any task while the scheduler is paused
+  sched.pause_all();
+  let paused_at = count.load(Ordering::Acquire);
+  unsafe { libc::usleep(10_000); }
+  assert_eq!(count.load(Ordering::Acquire), paused_at);
+  sched.resume_all();
+  assert!(wait_for(&count, paused_at+1));
+  sched.stop();
+}
+
+#[test]
+fn change_rule_of_running_task() {
+  let mut sched = Scheduler::new();
+  sched.start();
+
+  let (task, count) = counting_source("Source");
+  let id = sched.add_task(task, SchedulingRule::Periodic(PeriodLengthInUsec(60_000_000))).unwrap();
+  assert!(wait_for(&count, 1));
+  assert!(sched.set_rule(&id, SchedulingRule::Loop).is_ok());
+  assert!(wait_for(&count, 100));
+
+  // waits for notifications after one more execution
+  assert!(sched.set_rule(&id, SchedulingRule::OnExternalEvent).is_ok());
+  unsafe { libc::usleep(10_000); }
+  let before = count.load(Ordering::Acquire);
+  unsafe { libc::usleep(10_000); }
+  assert_eq!(count.load(Ordering::Acquire), before);
+  assert!(sched.notify(&id).is_ok());
+  assert!(wait_for(&count, before+1));
+
+  match sched.stats().tasks[0].rule {
+    SchedulingRule::OnExternalEvent => {},
+    other => panic!("unexpected rule: {:?}", other),
+  }
+  sched.stop();
+}
+
+#[test]
+fn change_rule_to_on_message() {
+  let mut sched = Scheduler::new();
+  sched.start();
+
+  let count = Arc::new(AtomicUsize::new(0));
+  let last = Arc::new(AtomicUsize::new(0));
+  let shutdown = Arc::new(AtomicUsize::new(0));
+  let (source_task, mut source_out) = source::new("Source", 10, Box::new(CountingSource{ count: count.clone() }));
+  let mut sink_task = sink::new("Sink", Box::new(LastValueSink{ last: last.clone(), shutdown: shutdown.clone() }));
+  assert!(sink_task.connect(&mut source_out).is_ok());
+  let source_id = sched.add_task(source_task, SchedulingRule::OnExternalEvent).unwrap();
+  let sink_id = sched.add_task(sink_task, SchedulingRule::Periodic(PeriodLengthInUsec(60_000_000))).unwrap();
+
+  // both run once after they were added, then the periodic sink is not
+  // triggered by the messages
+  assert!(wait_for(&count, 1));
+  unsafe { libc::usleep(10_000); }
+  let seen = last.load(Ordering::Acquire);
+  assert!(sched.notify(&source_id).is_ok());
+  assert!(wait_for(&count, 2));
+  unsafe { libc::usleep(10_000); }
+  assert_eq!(last.load(Ordering::Acquire), seen);
+
+  // after the switch it gets the pending and the new messages
+  assert!(sched.set_rule(&sink_id, SchedulingRule::OnMessage).is_ok());
+  assert!(wait_for(&last, 2));
+  assert!(sched.notify(&source_id).is_ok());
+  assert!(wait_for(&last, 3));
+  sched.stop();
+}
+
+#[test]
+fn external_event_or_timeout() {
+  let mut sched = Scheduler::new();
+  sched.start();
+
+  let reasons = Arc::new(Mutex::new(Vec::new()));
+  let (task, _out) = source::new("Source", 10, Box::new(WakeReasonSource{ reasons: reasons.clone() }));
+  let id = sched.add_task(task, SchedulingRule::OnExternalEventOrTimeout(PeriodLengthInUsec(2000))).unwrap();
+
+  let count = |reason: WakeReason| {
+    reasons.lock().unwrap().iter().filter(|r| **r == reason).count()
+  };
+  for _i in 0..2000 {
+    if count(WakeReason::Timeout) >= 3 { break; }
+    unsafe { libc::usleep(1000); }
+  }
+  assert!(count(WakeReason::Timeout) >= 3);
+
+  let triggered = count(WakeReason::Triggered);
+  assert!(sched.notify(&id).is_ok());
+  for _i in 0..2000 {
+    if count(WakeReason::Triggered) > triggered { break; }
+    unsafe { libc::usleep(1000); }
+  }
+  assert_eq!(count(WakeReason::Triggered), triggered+1);
+  // only the first execution after adding the task
+  assert_eq!(count(WakeReason::Scheduled), 1);
+  assert_eq!(reasons.lock().unwrap()[0], WakeReason::Scheduled);
+  sched.stop();
+}
+
+#[test]
+fn one_shot_rules() {
+  let mut sched = Scheduler::new();
+  sched.start();
+
+  let start = Instant::now();
+  let (task, after_count) = counting_source("After");
+  sched.add_task(task, SchedulingRule::After(PeriodLengthInUsec(5_000))).unwrap();
+  let (task, at_count) = counting_source("At");
+  let at_id = sched.add_task(task, SchedulingRule::At(start + Duration::from_millis(10))).unwrap();
+
+  assert!(wait_for(&after_count, 1));
+  assert!(start.elapsed() >= Duration::from_millis(5));
+  assert!(wait_for(&at_count, 1));
+  assert!(start.elapsed() >= Duration::from_millis(10));
+
+  // they run only once, unless notified
+  unsafe { libc::usleep(20_000); }
+  assert_eq!(after_count.load(Ordering::Acquire), 1);
+  assert_eq!(at_count.load(Ordering::Acquire), 1);
+  assert!(sched.notify(&at_id).is_ok());
+  assert!(wait_for(&at_count, 2));
+  sched.stop();
+}
+
+#[test]
+fn periodic_aligned_skips_ticks() {
+  let mut sched = Scheduler::new();
+  sched.start();
+
+  let count = Arc::new(AtomicUsize::new(0));
+  let skipped = Arc::new(AtomicUsize::new(0));
+  let (task, _out) = source::new("Aligned", 10, Box::new(SlowStartSource{
+    count: count.clone(), skipped: skipped.clone() }));
+  let id = sched.add_task(task, SchedulingRule::PeriodicAligned(PeriodLengthInUsec(1_000))).unwrap();
+
+  // the slow first run misses at least four ticks
+  assert!(wait_for(&count, 5));
+  assert!(skipped.load(Ordering::Acquire) >= 4);
+  let stats = sched.stats();
+  assert_eq!(stats.tasks[0].id, id);
+  assert!(stats.tasks[0].skipped_ticks >= 4);
+  sched.stop();
+}
+
+#[test]
+fn virtual_time_periodic_and_timeout() {
+  let mut sched = Scheduler::new_with_virtual_time();
+
+  let (task, count) = counting_source("Periodic");
+  sched.add_task(task, SchedulingRule::Periodic(PeriodLengthInUsec(1000))).unwrap();
+  let reasons = Arc::new(Mutex::new(Vec::new()));
+  let (task, _out) = source::new("Timeout", 10, Box::new(WakeReasonSource{ reasons: reasons.clone() }));
+  let timeout_id = sched.add_task(task, SchedulingRule::OnExternalEventOrTimeout(PeriodLengthInUsec(1500))).unwrap();
+
+  // both run once after they were added
+  assert_eq!(sched.run_until_idle(), 2);
+  assert_eq!(sched.step(), 0);
+
+  sched.advance_time(PeriodLengthInUsec(999));
+  assert_eq!(sched.step(), 0);
+  sched.advance_time(PeriodLengthInUsec(1));
+  assert_eq!(sched.step(), 1);
+  assert_eq!(count.load(Ordering::Acquire), 2);
+
+  sched.advance_time(PeriodLengthInUsec(500));
+  assert_eq!(sched.step(), 1);
+  assert_eq!(*reasons.lock().unwrap(), vec![WakeReason::Scheduled, WakeReason::Timeout]);
+  assert_eq!(sched.time(), PeriodLengthInUsec(1500));
+
+  // so does the first execution after the rule was changed
+  assert!(sched.set_rule(&timeout_id, SchedulingRule::OnMessageOrTimeout(PeriodLengthInUsec(1500))).is_ok());
+  assert_eq!(sched.step(), 1);
+  assert!(sched.notify(&timeout_id).is_ok());
+  assert_eq!(sched.step(), 1);
+  assert_eq!(*reasons.lock().unwrap(), vec![WakeReason::Scheduled, WakeReason::Timeout,
+                                            WakeReason::Scheduled, WakeReason::Triggered]);
+}
+
+#[test]
+fn virtual_time_pipeline() {
+  let mut sched = Scheduler::new_with_virtual_time();
+
+  let count = Arc::new(AtomicUsize::new(0));
+  let last = Arc::new(AtomicUsize::new(0));
+  let shutdown = Arc::new(AtomicUsize::new(0));
+  let (source_task, mut source_out) = source::new("Source", 10, Box::new(CountingSource{ count: count.clone() }));
+  let mut sink_task = sink::new("Sink", Box::new(LastValueSink{ last: last.clone(), shutdown: shutdown.clone() }));
+  assert!(sink_task.connect(&mut source_out).is_ok());
+  let source_id = sched.add_task(source_task, SchedulingRule::OnExternalEvent).unwrap();
+  sched.add_task(sink_task, SchedulingRule::OnMessage).unwrap();
+  // both run once, then the source's first message triggers the sink
+  assert_eq!(sched.run_until_idle(), 3);
+  assert_eq!(last.load(Ordering::Acquire), 1);
+
+  // the message is passed on in the next step
+  assert!(sched.notify(&source_id).is_ok());
+  assert_eq!(sched.step(), 1);
+  assert_eq!(last.load(Ordering::Acquire), 1);
+  assert_eq!(sched.step(), 1);
+  assert_eq!(last.load(Ordering::Acquire), 2);
+  assert_eq!(sched.step(), 0);
+}
+
+#[test]
+fn run_once_on_calling_thread() {
+  let mut sched = Scheduler::new();
+
+  let (task, periodic_count) = counting_source("Periodic");
+  sched.add_task(task, SchedulingRule::Periodic(PeriodLengthInUsec(2_000))).unwrap();
+  let (task, event_count) = counting_source("Event");
+  let event_id = sched.add_task(task, SchedulingRule::OnExternalEvent).unwrap();
+
+  let result = sched.run_once();
+  assert_eq!(result.executed, 2);
+  match result.next_timer {
+    Some(PeriodLengthInUsec(us)) => assert!(us <= 2_000),
+    None => panic!("the periodic task has a timer"),
+  }
+  assert_eq!(sched.poll(Duration::from_millis(10)).executed, 0);
+
+  // the caller waits for the timer
+  thread::sleep(Duration::from_millis(3));
+  assert_eq!(sched.run_once().executed, 1);
+  assert_eq!(periodic_count.load(Ordering::Acquire), 2);
+
+  assert!(sched.notify(&event_id).is_ok());
+  assert_eq!(sched.poll(Duration::from_millis(10)).executed, 1);
+  assert_eq!(event_count.load(Ordering::Acquire), 2);
+}
+
+#[test]
+fn readable_fd_triggers_task() {
+  let mut sched = Scheduler::new();
+  sched.start();
+
+  let mut fds = [0 as libc::c_int; 2];
+  assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK) }, 0);
+  let count = Arc::new(AtomicUsize::new(0));
+  let bytes = Arc::new(AtomicUsize::new(0));
+  let (task, _out) = source::new("Reader", 10, Box::new(ReadingSource{
+    fd: fds[0], count: count.clone(), bytes: bytes.clone() }));
+  let id = sched.add_task(task, SchedulingRule::OnReadable(fds[0])).unwrap();
+
+  // executed once after it was added, then only when there is data
+  assert!(wait_for(&count, 1));
+  unsafe { libc::usleep(10_000); }
+  assert_eq!(count.load(Ordering::Acquire), 1);
+  for i in 1..4 {
+    assert_eq!(unsafe { libc::write(fds[1], b"data".as_ptr() as *const libc::c_void, 4) }, 4);
+    assert!(wait_for(&bytes, 4*i));
+  }
+  unsafe { libc::usleep(10_000); }
+  assert!(count.load(Ordering::Acquire) <= 4);
+
+  // a removed task is not triggered anymore
+  let _task = sched.remove_task(&id).unwrap();
+  let executed = count.load(Ordering::Acquire);
+  assert_eq!(unsafe { libc::write(fds[1], b"data".as_ptr() as *const libc::c_void, 4) }, 4);
+  unsafe { libc::usleep(10_000); }
+  assert_eq!(count.load(Ordering::Acquire), executed);
+  sched.stop();
+  unsafe {
+    libc::close(fds[0]);
+    libc::close(fds[1]);
+  }
+}
+
+#[test]
+fn timerfd_periodic_task() {
+  let mut sched = Scheduler::new();
+  sched.set_periodic_timer(PeriodicTimer::TimerFd);
+  sched.start();
+
+  let count = Arc::new(AtomicUsize::new(0));
+  let (task, _out) = source::new("Timed", 10, Box::new(CountingSource{ count: count.clone() }));
+  let id = sched.add_task(task, SchedulingRule::Periodic(PeriodLengthInUsec(1000))).unwrap();
+  assert!(wait_for(&count, 5));
+
+  // switched back to the ticker when the rule changes
+  sched.set_periodic_timer(PeriodicTimer::Ticker);
+  assert!(sched.set_rule(&id, SchedulingRule::OnExternalEvent).is_ok());
+  unsafe { libc::usleep(10_000); }
+  let executed = count.load(Ordering::Acquire);
+  unsafe { libc::usleep(10_000); }
+  assert_eq!(count.load(Ordering::Acquire), executed);
+  sched.stop();
+}
+
+#[test]
+fn signal_triggers_task() {
+  // the signal must be blocked in all threads of the process, which is
+  // only doable in a child process without the other tests' threads
+  let pid = unsafe { libc::fork() };
+  assert!(pid >= 0);
+  if pid == 0 {
+    let mut sched = Scheduler::new();
+    let count = Arc::new(AtomicUsize::new(0));
+    let (task, _out) = source::new("Signaled", 10, Box::new(CountingSource{ count: count.clone() }));
+    let ok = sched.add_task(task, SchedulingRule::OnSignal(libc::SIGUSR1)).is_ok();
+    sched.start();
+    let mut result = 1;
+    if ok && wait_for(&count, 1) {
+      unsafe { libc::kill(libc::getpid(), libc::SIGUSR1); }
+      if wait_for(&count, 2) {
+        result = 0;
+      }
+    }
+    sched.stop();
+    unsafe { libc::_exit(result); }
+  }
+  let mut status = 0;
+  assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+  assert!(libc::WIFEXITED(status));
+  assert_eq!(libc::WEXITSTATUS(status), 0);
+}
+
+fn signal_blocked(signum: i32) -> bool {
+  unsafe {
+    let mut mask : libc::sigset_t = ::std::mem::zeroed();
+    libc::pthread_sigmask(libc::SIG_BLOCK, ::std::ptr::null(), &mut mask);
+    libc::sigismember(&mask, signum) == 1
+  }
+}
+
+// counts the executions on threads that block the signal
+struct SignalMaskSource {
+  blocked: Arc<AtomicUsize>,
+}
+
+impl source::Source for SignalMaskSource {
+  type OutputValue = usize;
+  type OutputError = &'static str;
+
+  fn process(&mut self,
+             _output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             _stop: &mut bool)
+  {
+    if signal_blocked(libc::SIGUSR2) {
+      self.blocked.fetch_add(1, Ordering::AcqRel);
+    }
+  }
+}
+
+#[test]
+fn signals_are_blocked_on_the_scheduler_threads() {
+  // registered on another thread, which the executors don't inherit from
+  let (mut sched, id) = thread::spawn(|| {
+    let mut sched = Scheduler::new();
+    let (task, _out) = source::new("Signaled", 10, Box::new(CountingSource{ count: Arc::new(AtomicUsize::new(0)) }));
+    let id = sched.add_task(task, SchedulingRule::OnSignal(libc::SIGUSR2)).unwrap();
+    assert!(signal_blocked(libc::SIGUSR2));
+    (sched, id)
+  }).join().unwrap();
+  assert!(!signal_blocked(libc::SIGUSR2));
+
+  let blocked = Arc::new(AtomicUsize::new(0));
+  let (task, _out) = source::new("Mask", 10, Box::new(SignalMaskSource{ blocked: blocked.clone() }));
+  sched.add_task(task, SchedulingRule::Loop).unwrap();
+  sched.start_with_threads(2);
+  assert!(wait_for(&blocked, 5));
+
+  // the running executors cannot block more signals
+  let (task, _out) = source::new("Late", 10, Box::new(CountingSource{ count: Arc::new(AtomicUsize::new(0)) }));
+  match sched.add_task(task, SchedulingRule::OnSignal(libc::SIGUSR2)) {
+    Err(Error::Busy) => {},
+    _                => { panic!("the signal was registered after the start"); }
+  }
+  sched.stop();
+
+  // removing the task restores the mask of the calling thread
+  let (task, _out) = source::new("Local", 10, Box::new(CountingSource{ count: Arc::new(AtomicUsize::new(0)) }));
+  let mut local = Scheduler::new();
+  let local_id = local.add_task(task, SchedulingRule::OnSignal(libc::SIGUSR2)).unwrap();
+  assert!(signal_blocked(libc::SIGUSR2));
+  assert!(local.remove_task(&local_id).is_ok());
+  assert!(!signal_blocked(libc::SIGUSR2));
+  assert!(sched.remove_task(&id).is_ok());
+}
+
+// a minimal executor for the tests of the async adapters
+struct ThreadWaker {
+  thread: thread::Thread,
+}
+
+impl Wake for ThreadWaker {
+  fn wake(self: Arc<Self>) {
+    self.thread.unpark();
+  }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+  let waker = Waker::from(Arc::new(ThreadWaker{ thread: thread::current() }));
+  let mut cx = Context::from_waker(&waker);
+  let mut future = Box::pin(future);
+  loop {
+    if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+      return output;
+    }
+    thread::park_timeout(Duration::from_secs(2));
+  }
+}
+
+// completes when the value is set from the outside
+struct ValueFuture {
+  state: Arc<Mutex<(Option<usize>, Option<Waker>)>>,
+}
+
+impl Future for ValueFuture {
+  type Output = usize;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<usize> {
+    let mut state = self.state.lock().unwrap();
+    match state.0.take() {
+      Some(value) => Poll::Ready(value),
+      None        => { state.1 = Some(cx.waker().clone()); Poll::Pending },
+    }
+  }
+}
+
+#[test]
+fn future_source_to_receiver_stream() {
+  let mut sched = Scheduler::new();
+  sched.start();
+
+  let state = Arc::new(Mutex::new((None, None)));
+  let (task, out) = stream::future_source::<usize, &'static str, _>("Async", 10,
+    ValueFuture{ state: state.clone() });
+  assert!(sched.add_task(task, SchedulingRule::OnExternalEvent).is_ok());
+  let mut rx = sched.receiver_stream(*out).unwrap();
+
+  let setter = state.clone();
+  let t = thread::spawn(move || {
+    thread::sleep(Duration::from_millis(10));
+    let waker = {
+      let mut state = setter.lock().unwrap();
+      state.0 = Some(42);
+      state.1.take()
+    };
+    // the task was executed once after it was added, so it is waiting
+    waker.unwrap().wake();
+  });
+  match block_on(stream::next(&mut rx)) {
+    Some(Message::Value(v)) => assert_eq!(v, 42),
+    _ => panic!("unexpected item"),
+  }
+  t.join().unwrap();
+  sched.stop();
+}
+
+#[test]
+fn dropped_receiver_stream_is_not_kept() {
+  let mut sched = Scheduler::new_with_virtual_time();
+  let count = Arc::new(AtomicUsize::new(0));
+  let (task, out) = source::new("Counter", 10, Box::new(CountingSource{ count: count.clone() }));
+  assert!(sched.add_task(task, SchedulingRule::Loop).is_ok());
+  let mut rx = sched.receiver_stream(*out).unwrap();
+
+  let thread_waker = Arc::new(ThreadWaker{ thread: thread::current() });
+  {
+    let waker = Waker::from(thread_waker.clone());
+    let mut cx = Context::from_waker(&waker);
+    assert!(stream::Stream::poll_next(Pin::new(&mut rx), &mut cx).is_pending());
+  }
+  assert_eq!(Arc::strong_count(&thread_waker), 2);
+
+  // the sender doesn't keep the waker of the dropped stream
+  drop(rx);
+  assert_eq!(Arc::strong_count(&thread_waker), 1);
+  assert_eq!(sched.step(), 1);
+}
+
+#[test]
+fn stream_source_forwards_items() {
+  let mut sched = Scheduler::new();
+  sched.start();
+
+  // counter -> stream -> stream source -> stream
+  let count = Arc::new(AtomicUsize::new(0));
+  let (counter, counter_out) = source::new("Counter", 100, Box::new(CountingSource{ count: count.clone() }));
+  assert!(sched.add_task(counter, SchedulingRule::Periodic(PeriodLengthInUsec(1000))).is_ok());
+  let counter_rx = sched.receiver_stream(*counter_out).unwrap();
+
+  let (forwarder, out) = stream::stream_source::<Message<usize, &'static str>, &'static str, _>(
+    "Forwarder", 100, counter_rx);
+  assert!(sched.add_task(forwarder, SchedulingRule::OnExternalEvent).is_ok());
+  let mut rx = sched.receiver_stream(*out).unwrap();
+
+  let mut expected = None;
+  for _i in 0..5 {
+    match block_on(stream::next(&mut rx)) {
+      Some(Message::Value(Message::Value(v))) => {
+        if let Some(e) = expected {
+          assert_eq!(v, e);
+        }
+        expected = Some(v+1);
+      },
+      _ => panic!("unexpected item"),
+    }
+  }
+  sched.stop();
+}
+
+struct SleepingSource {
+  count: Arc<AtomicUsize>,
+}
+
+impl source::Source for SleepingSource {
+  type OutputValue = usize;
+  type OutputError = &'static str;
+
+  fn process(&mut self,
+             output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             _stop: &mut bool)
+  {
+    thread::sleep(Duration::from_millis(20));
+    let count = self.count.fetch_add(1, Ordering::AcqRel);
+    output.put(|v| *v = Some(Message::Value(count)));
+  }
+}
+
+// blocks in process while the gate is closed
+struct GatedSource {
+  started: Arc<AtomicUsize>,
+  gate:    Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl source::Source for GatedSource {
+  type OutputValue = usize;
+  type OutputError = &'static str;
+
+  fn process(&mut self,
+             output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             _stop: &mut bool)
+  {
+    let count = self.started.fetch_add(1, Ordering::AcqRel);
+    let mut open = self.gate.0.lock().unwrap();
+    while !*open {
+      open = self.gate.1.wait(open).unwrap();
+    }
+    output.put(|v| *v = Some(Message::Value(count)));
+  }
+}
+
+fn set_gate(gate: &Arc<(Mutex<bool>, Condvar)>, open: bool) {
+  *gate.0.lock().unwrap() = open;
+  gate.1.notify_all();
+}
+
+#[test]
+fn blocking_tasks_run_on_their_pool() {
+  let mut sched = Scheduler::new();
+  sched.start_with_threads(1);
+
+  let started = Arc::new(AtomicUsize::new(0));
+  let fast = Arc::new(AtomicUsize::new(0));
+  let gate = Arc::new((Mutex::new(false), Condvar::new()));
+  let (gated_task, _gated_out) = source::new("Gated", 10, Box::new(GatedSource{ started: started.clone(), gate: gate.clone() }));
+  let (fast_task, _fast_out) = source::new("Fast", 10, Box::new(CountingSource{ count: fast.clone() }));
+  let gated_id = sched.add_task(gated_task, SchedulingRule::Blocking).unwrap();
+  assert!(sched.add_task(fast_task, SchedulingRule::Periodic(PeriodLengthInUsec(1000))).is_ok());
+
+  // the only executor is not stalled by the blocked task
+  assert!(wait_for(&started, 1));
+  assert!(wait_for(&fast, 5));
+
+  // back on the executor it stalls the other task
+  set_gate(&gate, true);
+  assert_eq!(sched.stats().executors.len(), 2);
+  assert!(sched.set_rule(&gated_id, SchedulingRule::Loop).is_ok());
+  set_gate(&gate, false);
+  let started_before = started.load(Ordering::Acquire);
+  assert!(wait_for(&started, started_before+1));
+  let fast_before = fast.load(Ordering::Acquire);
+  thread::sleep(Duration::from_millis(20));
+  assert_eq!(fast.load(Ordering::Acquire), fast_before);
+
+  set_gate(&gate, true);
+  sched.stop();
+}
+
+#[test]
+fn blocked_task_does_not_hold_up_the_stats() {
+  let mut sched = Scheduler::new();
+  sched.start_with_threads(1);
+
+  let started = Arc::new(AtomicUsize::new(0));
+  let gate = Arc::new((Mutex::new(false), Condvar::new()));
+  let (gated_task, _gated_out) = source::new("Gated", 10, Box::new(GatedSource{ started: started.clone(), gate: gate.clone() }));
+  let gated_id = sched.add_task(gated_task, SchedulingRule::Blocking).unwrap();
+  assert!(wait_for(&started, 1));
+
+  // the first execution is still blocked
+  let stats = sched.stats();
+  assert_eq!(stats.tasks.len(), 1);
+  assert_eq!(stats.tasks[0].id, gated_id);
+  assert_eq!(stats.tasks[0].exec_count, 0);
+  assert_eq!(stats.tasks[0].output_positions.len(), 1);
+  assert_eq!(sched.tasks()[0].id, gated_id);
+  sched.reset_latency();
+  sched.set_budget(ExecBudget{ max_messages: Some(1), max_ns: None });
+  assert!(sched.set_task_budget(&gated_id, None).is_ok());
+
+  set_gate(&gate, true);
+  sched.stop();
+}
+
+#[test]
+fn trace_of_pipeline() {
+  let mut sched = Scheduler::new_with_virtual_time();
+
+  let count = Arc::new(AtomicUsize::new(0));
+  let last = Arc::new(AtomicUsize::new(0));
+  let shutdown = Arc::new(AtomicUsize::new(0));
+  let (source_task, mut source_out) = source::new("Source", 10, Box::new(CountingSource{ count: count.clone() }));
+  let mut sink_task = sink::new("Sink", Box::new(LastValueSink{ last: last.clone(), shutdown: shutdown.clone() }));
+  assert!(sink_task.connect(&mut source_out).is_ok());
+  let source_id = sched.add_task(source_task, SchedulingRule::OnExternalEvent).unwrap();
+  sched.add_task(sink_task, SchedulingRule::OnMessage).unwrap();
+  assert_eq!(sched.run_until_idle(), 3);
+
+  // only the executions while tracing are recorded
+  sched.start_tracing(2);
+  assert!(sched.notify(&source_id).is_ok());
+  assert_eq!(sched.run_until_idle(), 2);
+  sched.stop_tracing();
+  assert!(sched.notify(&source_id).is_ok());
+  assert_eq!(sched.run_until_idle(), 2);
+
+  let json = sched.trace_json();
+  assert!(json.starts_with("{\"traceEvents\":["));
+  assert!(json.contains("\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":0,\"args\":{\"name\":\"caller\",\"dropped\":0}"));
+  assert!(json.contains("\"name\":\"Source\",\"cat\":\"task\",\"ph\":\"X\""));
+  assert!(json.contains("\"outputs\":[1]}"));
+  assert!(json.contains("\"name\":\"Sink\""));
+  assert_eq!(json.matches("\"ph\":\"X\"").count(), 2);
+
+  // a full buffer drops the executions
+  sched.start_tracing(1);
+  assert!(sched.notify(&source_id).is_ok());
+  assert_eq!(sched.run_until_idle(), 2);
+  let json = sched.trace_json();
+  assert_eq!(json.matches("\"ph\":\"X\"").count(), 1);
+  assert!(json.contains("\"dropped\":1"));
+}
+
+#[test]
+fn latency_histograms() {
+  let mut sched = Scheduler::new_with_virtual_time();
+
+  let (task, _count) = counting_source("Periodic");
+  sched.add_task(task, SchedulingRule::Periodic(PeriodLengthInUsec(1000))).unwrap();
+  let (task, _count) = counting_source("Event");
+  let event_id = sched.add_task(task, SchedulingRule::OnExternalEvent).unwrap();
+  assert_eq!(sched.run_until_idle(), 2);
+
+  // 500 usec late, and notified 300 usec before the execution
+  sched.advance_time(PeriodLengthInUsec(1200));
+  assert!(sched.notify(&event_id).is_ok());
+  sched.advance_time(PeriodLengthInUsec(300));
+  assert_eq!(sched.step(), 2);
+
+  let stats = sched.stats();
+  let periodic = stats.tasks.iter().find(|t| t.name == "Periodic").unwrap();
+  let event = stats.tasks.iter().find(|t| t.name == "Event").unwrap();
+  assert_eq!(periodic.exec_latency.count, 2);
+  assert!(periodic.exec_latency.p50 <= periodic.exec_latency.max);
+  assert_eq!(periodic.exec_latency.max, periodic.max_exec_ns);
+  // the first executions were not delayed by anything
+  assert_eq!(periodic.delay_latency.count, 1);
+  assert_eq!(periodic.delay_latency.max, 500_000);
+  assert!(periodic.delay_latency.p50 >= 375_000 && periodic.delay_latency.p50 <= 500_000);
+  assert_eq!(event.delay_latency.count, 1);
+  assert_eq!(event.delay_latency.max, 300_000);
+  assert_eq!(event.delay_latency.p999, 300_000);
+
+  sched.reset_latency();
+  let stats = sched.stats();
+  for task in stats.tasks {
+    assert_eq!(task.exec_latency, LatencyPercentiles::default());
+    assert_eq!(task.delay_latency, LatencyPercentiles::default());
+  }
+}
+
+#[test]
+fn watchdog_reports_stuck_task() {
+  let mut sched = Scheduler::new();
+  sched.start_with_threads(1);
+  sched.set_watchdog(Some(PeriodLengthInUsec(5_000)), WatchdogAction::MarkFailed);
+
+  let slow = Arc::new(AtomicUsize::new(0));
+  let (task, _out) = source::new("Stuck", 10, Box::new(SleepingSource{ count: slow.clone() }));
+  let id = sched.add_task(task, SchedulingRule::OnExternalEvent).unwrap();
+  let (task, fast) = counting_source("Quick");
+  let quick_id = sched.add_task(task, SchedulingRule::OnExternalEvent).unwrap();
+  assert!(sched.set_task_watchdog(&quick_id, None).is_ok());
+
+  assert!(wait_for(&slow, 1));
+  assert!(wait_for(&fast, 1));
+  let stuck = sched.stuck_tasks();
+  assert_eq!(stuck.len(), 1);
+  assert_eq!(stuck[0].id, id);
+  assert_eq!(stuck[0].name, "Stuck");
+  assert_eq!(stuck[0].executor, 0);
+  assert!(stuck[0].running_us > 5_000);
+  // reported once
+  assert!(sched.stuck_tasks().is_empty());
+
+  // failed when it returned
+  thread::sleep(Duration::from_millis(10));
+  let failed = sched.failed_tasks();
+  assert_eq!(failed, vec![(id, String::from("stuck in process"))]);
+  sched.stop();
+}
+
+#[test]
+fn watchdog_keeps_the_last_reports() {
+  let watchdog = super::watchdog::new();
+  for i in 0..1100 {
+    watchdog.push_event(StuckTask{
+      id:           TaskId(i),
+      name:         String::from("Stuck"),
+      executor:     0,
+      thread_name:  None,
+      running_us:   0,
+    });
+  }
+  let events = watchdog.take_events();
+  assert_eq!(events.len(), 1024);
+  assert_eq!(events[0].id, TaskId(76));
+  assert_eq!(events[1023].id, TaskId(1099));
+  assert!(watchdog.take_events().is_empty());
+}
+
+// writes a burst of values on each execution
+struct BurstSource {
+  next:  usize,
+}
+
+impl source::Source for BurstSource {
+  type OutputValue = usize;
+  type OutputError = &'static str;
+
+  fn process(&mut self,
+             output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             _stop: &mut bool)
+  {
+    for _i in 0..5 {
+      let value = self.next;
+      output.put(|v| *v = Some(Message::Value(value)));
+      self.next += 1;
+    }
+  }
+}
+
+// records the values received in each execution and the budget
+struct BatchSink {
+  batches:  Arc<Mutex<Vec<Vec<usize>>>>,
+  budgets:  Arc<Mutex<Vec<ExecBudget>>>,
+}
+
+impl sink::Sink for BatchSink {
+  type InputValue = usize;
+  type InputError = &'static str;
+
+  fn process(&mut self,
+             input: &mut ChannelWrapper<Self::InputValue, Self::InputError>,
+             _stop: &mut bool)
+  {
+    let mut batch = Vec::new();
+    if let &mut ChannelWrapper::ConnectedReceiver(_, ref mut receiver, _) = input {
+      for m in receiver.iter() {
+        if let Message::Value(v) = m {
+          batch.push(v);
+        }
+      }
+    }
+    assert!(!budget_expired());
+    self.budgets.lock().unwrap().push(budget());
+    self.batches.lock().unwrap().push(batch);
+  }
+}
+
+#[test]
+fn budget_caps_messages_per_execution() {
+  let mut sched = Scheduler::new_with_virtual_time();
+  let default = ExecBudget{ max_messages: None, max_ns: Some(1_000_000_000) };
+  sched.set_budget(default);
+
+  let batches = Arc::new(Mutex::new(Vec::new()));
+  let budgets = Arc::new(Mutex::new(Vec::new()));
+  let (source_task, mut source_out) = source::new("Burst", 10, Box::new(BurstSource{ next: 0 }));
+  let mut sink_task = sink::new("Batches", Box::new(BatchSink{ batches: batches.clone(), budgets: budgets.clone() }));
+  assert!(sink_task.connect(&mut source_out).is_ok());
+  let source_id = sched.add_task(source_task, SchedulingRule::OnExternalEvent).unwrap();
+  let sink_id = sched.add_task(sink_task, SchedulingRule::OnMessage).unwrap();
+  let capped = ExecBudget{ max_messages: Some(2), max_ns: None };
+  assert!(sched.set_task_budget(&sink_id, Some(capped)).is_ok());
+  sched.run_until_idle();
+
+  // the burst is handed over in three executions, nothing is lost
+  {
+    let batches = batches.lock().unwrap();
+    let received : Vec<Vec<usize>> = batches.iter().filter(|b| !b.is_empty()).cloned().collect();
+    assert_eq!(received, vec![vec![0, 1], vec![2, 3], vec![4]]);
+    assert!(budgets.lock().unwrap().iter().all(|b| *b == capped));
+  }
+
+  // back to the scheduler's budget, which doesn't cap the messages
+  assert!(sched.set_task_budget(&sink_id, None).is_ok());
+  batches.lock().unwrap().clear();
+  budgets.lock().unwrap().clear();
+  assert!(sched.notify(&source_id).is_ok());
+  sched.run_until_idle();
+  {
+    let batches = batches.lock().unwrap();
+    let received : Vec<Vec<usize>> = batches.iter().filter(|b| !b.is_empty()).cloned().collect();
+    assert_eq!(received, vec![vec![5, 6, 7, 8, 9]]);
+    assert!(budgets.lock().unwrap().iter().all(|b| *b == default));
+  }
+}
+
+#[test]
+fn budget_runs_periodic_task_again_right_away() {
+  let mut sched = Scheduler::new_with_virtual_time();
+
+  let batches = Arc::new(Mutex::new(Vec::new()));
+  let budgets = Arc::new(Mutex::new(Vec::new()));
+  let (source_task, mut source_out) = source::new("Burst", 10, Box::new(BurstSource{ next: 0 }));
+  let mut sink_task = sink::new("Batches", Box::new(BatchSink{ batches: batches.clone(), budgets: budgets.clone() }));
+  assert!(sink_task.connect(&mut source_out).is_ok());
+  let sink_id = sched.add_task(sink_task, SchedulingRule::Periodic(PeriodLengthInUsec(1_000_000))).unwrap();
+  sched.run_until_idle();
+  sched.add_task(source_task, SchedulingRule::OnExternalEvent).unwrap();
+  sched.run_until_idle();
+  assert!(sched.set_task_budget(&sink_id, Some(ExecBudget{ max_messages: Some(2), max_ns: None })).is_ok());
+
+  // the held back messages don't wait for the next period
+  sched.advance_time(PeriodLengthInUsec(1_000_000));
+  sched.run_until_idle();
+  let batches = batches.lock().unwrap();
+  let received : Vec<Vec<usize>> = batches.iter().filter(|b| !b.is_empty()).cloned().collect();
+  assert_eq!(received, vec![vec![0, 1], vec![2, 3], vec![4]]);
+}
+
+#[test]
+fn shutdown_drains_the_held_back_messages() {
+  let mut sched = Scheduler::new();
+  sched.start_with_threads(1);
+
+  let batches = Arc::new(Mutex::new(Vec::new()));
+  let budgets = Arc::new(Mutex::new(Vec::new()));
+  let (source_task, mut source_out) = source::new("Burst", 10, Box::new(BurstSource{ next: 0 }));
+  let mut sink_task = sink::new("Batches", Box::new(BatchSink{ batches: batches.clone(), budgets: budgets.clone() }));
+  assert!(sink_task.connect(&mut source_out).is_ok());
+  let source_id = sched.add_task(source_task, SchedulingRule::OnExternalEvent).unwrap();
+  let sink_id = sched.add_task(sink_task, SchedulingRule::OnMessage).unwrap();
+  assert!(sched.set_task_budget(&sink_id, Some(ExecBudget{ max_messages: Some(1), max_ns: None })).is_ok());
+  while batches.lock().unwrap().iter().all(|b| b.is_empty()) {
+    thread::sleep(Duration::from_millis(1));
+  }
+
+  // the rest of the burst waits in the backlog of the sink
+  let report = sched.shutdown(Duration::from_secs(5));
+  assert!(!report.timed_out);
+  assert_eq!(report.sources, vec![(source_id, String::from("Burst"))]);
+  assert_eq!(report.drained, vec![(sink_id, String::from("Batches"))]);
+  let received : Vec<usize> = batches.lock().unwrap().iter().flat_map(|b| b.clone()).collect();
+  assert_eq!(received, vec![0, 1, 2, 3, 4]);
+}
+
+#[test]
+fn shutdown_reports_the_dropped_messages() {
+  let mut sched = Scheduler::new();
+  sched.start_with_threads(1);
+
+  let batches = Arc::new(Mutex::new(Vec::new()));
+  let budgets = Arc::new(Mutex::new(Vec::new()));
+  let (source_task, mut source_out) = source::new("Burst", 10, Box::new(BurstSource{ next: 0 }));
+  let mut sink_task = sink::new("Batches", Box::new(BatchSink{ batches: batches.clone(), budgets: budgets.clone() }));
+  assert!(sink_task.connect(&mut source_out).is_ok());
+  // the sink is not message driven and doesn't run again before the
+  // timeout
+  let sink_id = sched.add_task(sink_task, SchedulingRule::Periodic(PeriodLengthInUsec(60_000_000))).unwrap();
+  while batches.lock().unwrap().is_empty() {
+    thread::sleep(Duration::from_millis(1));
+  }
+  let source_id = sched.add_task(source_task, SchedulingRule::OnExternalEvent).unwrap();
+  while sched.stats().tasks.iter().any(|t| t.id == source_id && t.exec_count == 0) {
+    thread::sleep(Duration::from_millis(1));
+  }
+
+  let report = sched.shutdown(Duration::from_millis(50));
+  assert!(report.timed_out);
+  assert!(report.drained.is_empty());
+  assert_eq!(report.dropped, vec![(sink_id, String::from("Batches"), 5)]);
+}
+
+// adds and notifies a new child task on each execution
+struct SpawningSource {
+  spawned:   Arc<AtomicUsize>,
+  children:  Arc<AtomicUsize>,
+}
+
+impl source::Source for SpawningSource {
+  type OutputValue = usize;
+  type OutputError = &'static str;
+
+  fn process(&mut self,
+             _output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             _stop: &mut bool)
+  {
+    let handle = super::handle().unwrap();
+    let n = self.spawned.fetch_add(1, Ordering::AcqRel);
+    let name = format!("Child-{}", n);
+    let (task, _out) = source::new(&name, 10, Box::new(CountingSource{ count: self.children.clone() }));
+    let id = handle.add_task(task, SchedulingRule::OnExternalEvent).unwrap();
+    assert!(handle.notify(&id).is_ok());
+    assert_eq!(handle.task_id(&name), Some(id));
+  }
+}
+
+#[test]
+fn handle_from_tasks_and_threads() {
+  let mut sched = Scheduler::new();
+  sched.start_with_threads(2);
+  let handle = sched.handle();
+  assert!(super::handle().is_none());
+
+  let spawned = Arc::new(AtomicUsize::new(0));
+  let children = Arc::new(AtomicUsize::new(0));
+  let (task, _out) = source::new("Acceptor", 10, Box::new(SpawningSource{ spawned: spawned.clone(), children: children.clone() }));
+  let acceptor = handle.add_task(task, SchedulingRule::OnExternalEvent).unwrap();
+  assert!(wait_for(&spawned, 1));
+
+  // another thread triggers the next child
+  let remote = handle.clone();
+  thread::spawn(move || {
+    assert!(remote.notify(&acceptor).is_ok());
+  }).join().unwrap();
+  assert!(wait_for(&spawned, 2));
+  assert!(wait_for(&children, 2));
+
+  assert_eq!(handle.task_name(&acceptor), Some(String::from("Acceptor")));
+  assert!(handle.task_id("Child-1").is_some());
+  assert_eq!(handle.task_id("Child-2"), None);
+  assert_eq!(handle.stats().tasks.len(), 3);
+  assert!(handle.failed_tasks().is_empty());
+  sched.stop();
+}
+
+#[test]
+fn consumers_added_while_their_sender_adds_tasks() {
+  let mut sched = Scheduler::new();
+  sched.start_with_threads(2);
+
+  let spawned = Arc::new(AtomicUsize::new(0));
+  let children = Arc::new(AtomicUsize::new(0));
+  let (task, _out) = source::new("Acceptor", 10, Box::new(SpawningSource{ spawned: spawned.clone(), children: children.clone() }));
+  sched.add_task(task, SchedulingRule::Loop).unwrap();
+  assert!(wait_for(&spawned, 1));
+
+  // the consumers register with the running acceptor
+  let last = Arc::new(AtomicUsize::new(0));
+  let shutdown = Arc::new(AtomicUsize::new(0));
+  for i in 0..100 {
+    let (_other, mut other_out) = source::new("Acceptor", 10, Box::new(CountingSource{ count: children.clone() }));
+    let mut sink_task = sink::new(&format!("Consumer-{}", i), Box::new(LastValueSink{ last: last.clone(), shutdown: shutdown.clone() }));
+    assert!(sink_task.connect(&mut other_out).is_ok());
+    assert!(sched.add_task(sink_task, SchedulingRule::OnMessage).is_ok());
+  }
+  let before = spawned.load(Ordering::Acquire);
+  assert!(wait_for(&spawned, before+1));
+  sched.stop();
+}
+
+type SharedOutput = Arc<Mutex<Option<Box<ChannelWrapper<usize, &'static str>>>>>;
+
+// connects a new sink to its own output on the first execution
+struct ConsumerSpawningSource {
+  out:       SharedOutput,
+  last:      Arc<AtomicUsize>,
+  shutdown:  Arc<AtomicUsize>,
+  next:      usize,
+}
+
+impl source::Source for ConsumerSpawningSource {
+  type OutputValue = usize;
+  type OutputError = &'static str;
+
+  fn process(&mut self,
+             output: &mut Sender<Message<Self::OutputValue, Self::OutputError>>,
+             _stop: &mut bool)
+  {
+    if let Some(mut out) = self.out.lock().unwrap().take() {
+      let mut sink_task = sink::new("Consumer", Box::new(LastValueSink{ last: self.last.clone(), shutdown: self.shutdown.clone() }));
+      assert!(sink_task.connect(&mut out).is_ok());
+      let handle = super::handle().unwrap();
+      assert!(handle.add_task(sink_task, SchedulingRule::OnMessage).is_ok());
+    }
+    let value = self.next;
+    self.next += 1;
+    output.put(|v| *v = Some(Message::Value(value)));
+  }
+}
+
+#[test]
+fn task_adds_consumer_of_its_output() {
+  let mut sched = Scheduler::new_with_virtual_time();
+  let out = Arc::new(Mutex::new(None));
+  let last = Arc::new(AtomicUsize::new(0));
+  let shutdown = Arc::new(AtomicUsize::new(0));
+  let (task, source_out) = source::new("Producer", 10, Box::new(ConsumerSpawningSource{
+    out: out.clone(), last: last.clone(), shutdown: shutdown.clone(), next: 0 }));
+  *out.lock().unwrap() = Some(source_out);
+  let id = sched.add_task(task, SchedulingRule::OnExternalEvent).unwrap();
+  sched.run_until_idle();
+  assert_eq!(last.load(Ordering::Acquire), 1);
+
+  // the consumer is triggered by the producer's messages
+  assert!(sched.notify(&id).is_ok());
+  assert_eq!(sched.run_until_idle(), 2);
+  assert_eq!(last.load(Ordering::Acquire), 2);
+}
+
+#[test]
+fn task_registry() {
+  let mut sched = Scheduler::new_with_virtual_time();
+
+  let last = Arc::new(AtomicUsize::new(0));
+  let shutdown = Arc::new(AtomicUsize::new(0));
+  let (source_task, mut source_out) = source::new("Source", 10, Box::new(BurstSource{ next: 0 }));
+  let mut sink_task = sink::new("Sink", Box::new(LastValueSink{ last: last.clone(), shutdown: shutdown.clone() }));
+  assert!(sink_task.connect(&mut source_out).is_ok());
+  // its sender is not added
+  let (_later_task, mut later_out) = source::new("Later", 10, Box::new(BurstSource{ next: 0 }));
+  let mut waiting_task = sink::new("Waiting", Box::new(LastValueSink{ last: last.clone(), shutdown: shutdown.clone() }));
+  assert!(waiting_task.connect(&mut later_out).is_ok());
+  let (bad_task, _bad_out) = source::new("Bad", 10, Box::new(PanickingSource{}));
+
+  let source_id = sched.add_task(source_task, SchedulingRule::OnExternalEvent).unwrap();
+  let sink_id = sched.add_task(sink_task, SchedulingRule::OnMessage).unwrap();
+  let waiting_id = sched.add_task(waiting_task, SchedulingRule::OnMessage).unwrap();
+  let bad_id = sched.add_task(bad_task, SchedulingRule::OnExternalEvent).unwrap();
+  sched.run_until_idle();
+  assert!(sched.pause(&waiting_id).is_ok());
+
+  assert_eq!(sched.task_id("Sink"), Some(sink_id));
+  assert_eq!(sched.task_id("Later"), None);
+
+  let channel = ChannelId{ sender_id: SenderChannelId(0), receiver_id: ReceiverChannelId(0) };
+  let tasks = sched.tasks();
+  let names : Vec<&str> = tasks.iter().map(|t| t.name.as_str()).collect();
+  assert_eq!(names, vec!["Source", "Sink", "Waiting", "Bad"]);
+  assert_eq!(tasks[0].id, source_id);
+  assert_eq!(tasks[0].state, TaskState::Running);
+  assert!(tasks[0].inputs.is_empty());
+  assert_eq!(tasks[0].outputs, vec![(channel, sink_id)]);
+  assert_eq!(tasks[1].inputs, vec![(channel, SenderName(String::from("Source")), Some(source_id))]);
+  assert!(tasks[1].outputs.is_empty());
+  match tasks[1].rule {
+    SchedulingRule::OnMessage => {},
+    rule => panic!("unexpected rule: {:?}", rule),
+  }
+  assert_eq!(tasks[2].state, TaskState::Paused);
+  assert_eq!(tasks[2].inputs, vec![(channel, SenderName(String::from("Later")), None)]);
+  assert_eq!(tasks[3].id, bad_id);
+  assert_eq!(tasks[3].state, TaskState::Failed);
+
+  // the burst reaches the sink
+  assert!(sched.notify_by_name("Source").is_ok());
+  sched.run_until_idle();
+  assert_eq!(last.load(Ordering::Acquire), 10);
+  assert!(sched.notify_by_name("Later").is_err());
+}
+
+#[test]
+fn task_registry_lists_outputs_of_any_rule() {
+  let mut sched = Scheduler::new_with_virtual_time();
+
+  let last = Arc::new(AtomicUsize::new(0));
+  let shutdown = Arc::new(AtomicUsize::new(0));
+  let (source_task, mut source_out) = source::new("Source", 10, Box::new(BurstSource{ next: 0 }));
+  let mut sink_task = sink::new("Polling", Box::new(LastValueSink{ last: last.clone(), shutdown: shutdown.clone() }));
+  assert!(sink_task.connect(&mut source_out).is_ok());
+  let source_id = sched.add_task(source_task, SchedulingRule::OnExternalEvent).unwrap();
+  let sink_id = sched.add_task(sink_task, SchedulingRule::Periodic(PeriodLengthInUsec(1000))).unwrap();
+
+  // the periodic sink doesn't register with the source, it is listed anyway
+  let channel = ChannelId{ sender_id: SenderChannelId(0), receiver_id: ReceiverChannelId(0) };
+  let tasks = sched.tasks();
+  assert_eq!(tasks[0].id, source_id);
+  assert_eq!(tasks[0].outputs, vec![(channel, sink_id)]);
+  assert_eq!(tasks[1].inputs, vec![(channel, SenderName(String::from("Source")), Some(source_id))]);
+  assert!(tasks[1].outputs.is_empty());
+}
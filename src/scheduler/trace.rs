@@ -0,0 +1,124 @@
+use std::cell::{UnsafeCell};
+use std::collections::{HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Instant};
+use super::super::{TaskId};
+
+// one execution of a task
+pub struct TraceEvent {
+  task:      TaskId,
+  // since the scheduler was created
+  start_ns:  u64,
+  dur_ns:    u64,
+  // how far each output channel's position advanced
+  advanced:  Vec<usize>,
+}
+
+// written by one executor thread only. the slots below len are complete
+// and not written anymore, so they can be read without locking.
+pub struct TraceBuffer {
+  tid:      usize,
+  epoch:    Instant,
+  slots:    Vec<UnsafeCell<Option<TraceEvent>>>,
+  len:      AtomicUsize,
+  dropped:  AtomicUsize,
+}
+
+unsafe impl Send for TraceBuffer { }
+unsafe impl Sync for TraceBuffer { }
+
+impl TraceBuffer {
+  pub fn tid(&self) -> usize {
+    self.tid
+  }
+
+  // called by the owner thread only. the events are dropped when the
+  // buffer is full.
+  pub fn record(&self,
+                task: TaskId,
+                start: Instant,
+                dur_ns: u64,
+                advanced: Vec<usize>)
+  {
+    let len = self.len.load(Ordering::Relaxed);
+    if len >= self.slots.len() {
+      self.dropped.fetch_add(1, Ordering::Relaxed);
+      return;
+    }
+    let since = start.duration_since(self.epoch);
+    let event = TraceEvent{
+      task,
+      start_ns:  since.as_secs() * 1_000_000_000 + since.subsec_nanos() as u64,
+      dur_ns,
+      advanced,
+    };
+    unsafe { *self.slots[len].get() = Some(event); }
+    self.len.store(len+1, Ordering::Release);
+  }
+
+  pub fn dropped(&self) -> usize {
+    self.dropped.load(Ordering::Relaxed)
+  }
+
+  fn events(&self) -> &[UnsafeCell<Option<TraceEvent>>] {
+    let len = self.len.load(Ordering::Acquire);
+    &self.slots[0..len]
+  }
+}
+
+pub fn new(tid: usize, epoch: Instant, capacity: usize) -> TraceBuffer {
+  TraceBuffer{
+    tid,
+    epoch,
+    slots:    (0..capacity).map(|_| UnsafeCell::new(None)).collect(),
+    len:      AtomicUsize::new(0),
+    dropped:  AtomicUsize::new(0),
+  }
+}
+
+fn escape(s: &str) -> String {
+  let mut escaped = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '"'  => escaped.push_str("\\\""),
+      '\\' => escaped.push_str("\\\\"),
+      c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+      c    => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+fn thread_name(tid: usize) -> String {
+  match tid {
+    0 => String::from("caller"),
+    t => format!("executor #{}", t-1),
+  }
+}
+
+// the Chrome Trace Event format: a complete (X) event per execution and a
+// name for each thread. the timestamps are in microseconds.
+pub fn to_json(buffers: &Vec<::std::sync::Arc<TraceBuffer>>,
+               names: &HashMap<TaskId, String>) -> String
+{
+  let mut events = Vec::new();
+  for buffer in buffers {
+    events.push(format!(
+      "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{},\"args\":{{\"name\":\"{}\",\"dropped\":{}}}}}",
+      buffer.tid(), thread_name(buffer.tid()), buffer.dropped()));
+    for slot in buffer.events() {
+      if let Some(ref e) = unsafe { &*slot.get() } {
+        let name = match names.get(&e.task) {
+          Some(name) => escape(name),
+          None       => format!("#{}", e.task.0),
+        };
+        let advanced : Vec<String> = e.advanced.iter().map(|a| a.to_string()).collect();
+        events.push(format!(
+          "{{\"name\":\"{}\",\"cat\":\"task\",\"ph\":\"X\",\"ts\":{}.{:03},\"dur\":{}.{:03},\"pid\":1,\"tid\":{},\"args\":{{\"id\":{},\"outputs\":[{}]}}}}",
+          name, e.start_ns/1000, e.start_ns%1000, e.dur_ns/1000, e.dur_ns%1000,
+          buffer.tid(), e.task.0, advanced.join(",")));
+      }
+    }
+  }
+  format!("{{\"traceEvents\":[{}],\"displayTimeUnit\":\"ns\"}}", events.join(",\n"))
+}
@@ -0,0 +1,54 @@
+use std::sync::{Arc, Mutex};
+use std::task::{Wake, Waker};
+use super::super::{TaskId};
+use super::handle::{SchedulerDataHandle};
+
+// notifies the task when an async operation that it polled can progress
+pub struct TaskWaker {
+  data:  SchedulerDataHandle,
+  id:    TaskId,
+}
+
+impl Wake for TaskWaker {
+  fn wake(self: Arc<Self>) {
+    self.data.notify(&self.id);
+  }
+
+  fn wake_by_ref(self: &Arc<Self>) {
+    self.data.notify(&self.id);
+  }
+}
+
+pub fn task_waker(data: SchedulerDataHandle, id: TaskId) -> Waker {
+  Waker::from(Arc::new(TaskWaker{ data, id }))
+}
+
+// the waker of an async reader of a task's output channel. the scheduler
+// wakes it after the task was executed and the channel's position advanced.
+pub struct OutputWaker {
+  waker: Mutex<Option<Waker>>,
+}
+
+impl OutputWaker {
+  pub fn register(&self, waker: &Waker) {
+    let mut current = self.waker.lock().unwrap();
+    let same = match *current {
+      Some(ref w) => w.will_wake(waker),
+      None        => false,
+    };
+    if !same {
+      *current = Some(waker.clone());
+    }
+  }
+
+  pub fn wake(&self) {
+    let waker = self.waker.lock().unwrap().take();
+    if let Some(w) = waker {
+      w.wake();
+    }
+  }
+}
+
+pub fn output_waker() -> Arc<OutputWaker> {
+  Arc::new(OutputWaker{ waker: Mutex::new(None) })
+}
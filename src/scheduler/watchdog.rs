@@ -0,0 +1,106 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex};
+use super::super::{TaskId, PeriodLengthInUsec, WatchdogAction, StuckTask};
+
+// the stuck executions kept until they are taken, the older ones are
+// dropped
+const MAX_EVENTS : usize = 1024;
+
+struct State {
+  threshold:  Option<PeriodLengthInUsec>,
+  action:     WatchdogAction,
+  // overrides the global threshold
+  per_task:   HashMap<TaskId, Option<PeriodLengthInUsec>>,
+  // the stuck executions not yet taken, and the ones already reported
+  // as (executor, task, start time)
+  events:     VecDeque<StuckTask>,
+  reported:   Vec<(usize, TaskId, usize)>,
+}
+
+pub struct Watchdog {
+  state:  Mutex<State>,
+}
+
+impl Watchdog {
+  pub fn set(&self,
+             threshold: Option<PeriodLengthInUsec>,
+             action: WatchdogAction)
+  {
+    let mut state = self.state.lock().unwrap();
+    state.threshold = threshold;
+    state.action = action;
+  }
+
+  pub fn set_task(&self,
+                  id: TaskId,
+                  threshold: Option<PeriodLengthInUsec>)
+  {
+    let mut state = self.state.lock().unwrap();
+    state.per_task.insert(id, threshold);
+  }
+
+  pub fn remove_task(&self, id: TaskId) {
+    let mut state = self.state.lock().unwrap();
+    state.per_task.remove(&id);
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    let state = self.state.lock().unwrap();
+    state.threshold.is_some() || state.per_task.values().any(|t| t.is_some())
+  }
+
+  pub fn action(&self) -> WatchdogAction {
+    self.state.lock().unwrap().action
+  }
+
+  // the executions over their threshold that were not reported yet.
+  // running holds (executor, task, start time) of the executions in
+  // progress.
+  pub fn check(&self,
+               running: &Vec<(usize, TaskId, usize)>,
+               now: usize) -> Vec<(usize, TaskId, usize)>
+  {
+    let mut state = self.state.lock().unwrap();
+    // forget the executions that ended
+    state.reported.retain(|r| running.contains(r));
+    let mut stuck = Vec::new();
+    for r in running {
+      let threshold = match state.per_task.get(&r.1) {
+        Some(t) => *t,
+        None    => state.threshold,
+      };
+      if let Some(t) = threshold {
+        if now.saturating_sub(r.2) > t.0 && !state.reported.contains(r) {
+          state.reported.push(*r);
+          stuck.push(*r);
+        }
+      }
+    }
+    stuck
+  }
+
+  pub fn push_event(&self, event: StuckTask) {
+    let mut state = self.state.lock().unwrap();
+    if state.events.len() == MAX_EVENTS {
+      state.events.pop_front();
+    }
+    state.events.push_back(event);
+  }
+
+  pub fn take_events(&self) -> Vec<StuckTask> {
+    let mut state = self.state.lock().unwrap();
+    state.events.drain(..).collect()
+  }
+}
+
+pub fn new() -> Watchdog {
+  Watchdog{
+    state: Mutex::new(State{
+      threshold:  None,
+      action:     WatchdogAction::Report,
+      per_task:   HashMap::new(),
+      events:     VecDeque::new(),
+      reported:   Vec::new(),
+    }),
+  }
+}
@@ -1,33 +1,144 @@
-use super::super::{Task, ChannelPosition, TaskId, SenderChannelId, ChannelId};
+use super::super::{Task, ChannelPosition, TaskId, SenderChannelId, ChannelId,
+  ReceiverChannelId, SchedulingRule, SenderName, ExecBudget};
 use super::prv::{Private};
+use super::{page, stats, context};
+use super::waker::{OutputWaker};
+use std::time::{Instant};
+use std::sync::{Arc, Weak};
+use std::cmp;
 
 pub struct TaskWrap {
   task:              Box<Task+Send>,
-  output_positions:  Vec<(ChannelPosition, TaskId)>,
+  rule:              SchedulingRule,
+  output_positions:  Vec<(ChannelPosition, Option<TaskId>)>,
+  dependents:        Vec<(ChannelId, TaskId)>,
+  // async readers of the output channels, dropped with their stream
+  output_wakers:     Vec<(SenderChannelId, Weak<OutputWaker>)>,
+  // the scheduler's default is used unless set for the task
+  budget:            Option<ExecBudget>,
+  // statistics
+  counters:          Arc<stats::TaskCounters>,
 }
 
 impl TaskWrap {
   #[inline(always)]
   pub fn execute(&mut self,
+                 id: TaskId,
                  has_dependents: bool,
                  stop: &mut bool,
                  private_data: &mut Private)
   {
+    let traced_positions = if private_data.is_tracing() {
+      Some(self.output_positions_now())
+    } else {
+      None
+    };
+    if let Some(budget) = self.counters.take_budget() {
+      self.budget = budget;
+    }
+    let start = Instant::now();
+    context::set_budget(self.budget.unwrap_or(private_data.budget()), start);
     self.task.execute(stop);
+    let diff = start.elapsed();
+    let diff_ns = diff.as_secs() * 1_000_000_000 + diff.subsec_nanos() as u64;
+    if let Some(before) = traced_positions {
+      let advanced = self.output_positions_now().iter().zip(before.iter()).map(|(now, before)| {
+        now.saturating_sub(*before)
+      }).collect();
+      private_data.record_trace(id, start, diff_ns, advanced);
+    }
+    self.counters.record_execution(start, diff_ns);
+    private_data.add_busy_ns(diff_ns);
+
     if has_dependents {
       let n_outputs = self.output_positions.len();
       let mut slice = self.output_positions.as_mut_slice();
+      let mut dropped_wakers = false;
       for i in 0..n_outputs {
         let old_position = slice[i].0;
         let new_position = self.task.output_channel_pos(SenderChannelId(i));
         if old_position.0 < new_position.0 {
-          private_data.save_trigger(slice[i].1);
+          if let Some(dep_id) = slice[i].1 {
+            private_data.save_trigger(dep_id);
+          }
+          for &(ch_id, ref waker) in self.output_wakers.iter() {
+            if ch_id.0 == i {
+              match waker.upgrade() {
+                Some(waker) => waker.wake(),
+                None        => { dropped_wakers = true; },
+              }
+            }
+          }
         }
-        slice[i].0 = self.task.output_channel_pos(SenderChannelId(i));
+        slice[i].0 = new_position;
+      }
+      if dropped_wakers {
+        self.prune_output_wakers();
       }
     }
   }
 
+  fn output_positions_now(&self) -> Vec<usize> {
+    (0..self.task.output_count()).map(|i| {
+      self.task.output_channel_pos(SenderChannelId(i)).0
+    }).collect()
+  }
+
+  // updates the channel positions in the statistics
+  pub fn update_positions(&self) {
+    self.counters.set_positions(input_positions(&*self.task), output_positions(&*self.task));
+  }
+
+  pub fn counters(&self) -> &Arc<stats::TaskCounters> {
+    &self.counters
+  }
+
+  pub fn record_delay(&mut self, delay_us: usize) {
+    self.counters.record_delay(delay_us as u64 * 1000);
+  }
+
+  // the number of grid ticks missed since the due time. only
+  // PeriodicAligned tasks skip ticks, the others are just late.
+  pub fn skip_ticks(&mut self, due: usize, now: usize) -> usize {
+    let skipped = match self.rule {
+      SchedulingRule::PeriodicAligned(period) if due > 0 && now > due => {
+        (now-due) / cmp::max(period.0, 1)
+      },
+      _ => 0,
+    };
+    if skipped > 0 {
+      self.counters.add_skipped_ticks(skipped as u64);
+    }
+    skipped
+  }
+
+  // the next execution time of a delayed task that was started at the
+  // given time
+  pub fn next_exec_at(&self, started: usize) -> usize {
+    match self.rule {
+      SchedulingRule::PeriodicAligned(period) => page::grid_point(started+1, period),
+      SchedulingRule::Periodic(period) => started + period.0,
+      // one-shot rules wait for a notification
+      _ => page::never(),
+    }
+  }
+
+  pub fn waits_for_fd(&self) -> bool {
+    matches!(self.rule, SchedulingRule::OnReadable(_) | SchedulingRule::OnWritable(_))
+  }
+
+  pub fn is_source(&self) -> bool {
+    self.task.input_count() == 0
+  }
+
+  pub fn shutdown(&mut self) {
+    self.task.shutdown();
+  }
+
+  pub fn restart(&mut self) -> bool {
+    self.task.restart()
+  }
+
   pub fn register_dependents(&mut self,
                              deps: Vec<(ChannelId, TaskId)>)
   {
@@ -37,16 +148,83 @@ impl TaskWrap {
       let ch_id = dep.0;
       let idx = ch_id.sender_id.0;
       if idx < n_pos {
-        slice[idx].1 = dep.1;
+        slice[idx].1 = Some(dep.1);
+        self.dependents.push(dep);
       }
     }
   }
+
+  pub fn unregister_dependent(&mut self,
+                              id: TaskId)
+  {
+    for pos in self.output_positions.iter_mut() {
+      if pos.1 == Some(id) {
+        pos.1 = None;
+      }
+    }
+    self.dependents.retain(|dep| dep.1 != id);
+  }
+
+  pub fn add_output_waker(&mut self,
+                          ch_id: SenderChannelId,
+                          waker: Arc<OutputWaker>)
+  {
+    self.prune_output_wakers();
+    self.output_wakers.push((ch_id, Arc::downgrade(&waker)));
+  }
+
+  // forgets the wakers of the dropped streams
+  fn prune_output_wakers(&mut self) {
+    self.output_wakers.retain(|w| w.1.strong_count() > 0);
+  }
+
+  pub fn dependents(&self) -> &Vec<(ChannelId, TaskId)> {
+    &self.dependents
+  }
+
+  pub fn task(&self) -> &(dyn Task+Send) {
+    &*self.task
+  }
+
+  pub fn rule(&self) -> SchedulingRule {
+    self.rule
+  }
+
+  // returns the previous rule
+  pub fn set_rule(&mut self, rule: SchedulingRule) -> SchedulingRule {
+    let old_rule = self.rule;
+    self.rule = rule;
+    self.counters.set_rule(rule);
+    old_rule
+  }
+
+  pub fn into_task(self) -> Box<dyn Task+Send> {
+    self.task
+  }
+}
+
+fn input_positions(task: &dyn Task) -> Vec<ChannelPosition> {
+  (0..task.input_count()).map(|i| task.input_channel_pos(ReceiverChannelId(i))).collect()
+}
+
+fn output_positions(task: &dyn Task) -> Vec<ChannelPosition> {
+  (0..task.output_count()).map(|i| task.output_channel_pos(SenderChannelId(i))).collect()
 }
 
-pub fn new(task: Box<Task+Send>) -> TaskWrap {
+pub fn new(task: Box<dyn Task+Send>,
+           rule: SchedulingRule,
+           inputs: Vec<(ChannelId, SenderName)>) -> TaskWrap
+{
   let n_outputs = task.output_count();
+  let positions = (input_positions(&*task), output_positions(&*task));
+  let counters = stats::new_task(task.name().clone(), inputs, rule, positions);
   TaskWrap{
-    task:              task,
-    output_positions:  vec![(ChannelPosition(0), TaskId(0)); n_outputs],
+    task,
+    rule,
+    output_positions:  vec![(ChannelPosition(0), None); n_outputs],
+    dependents:        Vec::new(),
+    output_wakers:     Vec::new(),
+    budget:            None,
+    counters:          Arc::new(counters),
   }
 }